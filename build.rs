@@ -0,0 +1,227 @@
+//! Generates `memory.x`, the `MEMORY` block that is included by `nrf52.ld`
+//!
+//! The FLASH and RAM sizes are selected by the chip variant feature, which only affects the
+//! memory layout, the rest of the crate targets the nRF52832. Regions that are used by
+//! other images are cut out of them with these environment variables, sizes are in bytes
+//! (decimal, `0x` hex or with a `K` suffix):
+//!     * `NRF52_FLASH_RESERVED_START`: start of FLASH, e.g. an MBR, a SoftDevice or a
+//!       bootloader that starts this application. The vector table is placed right after
+//!       it
+//!     * `NRF52_BOOTLOADER_SIZE`: bootloader at the end of FLASH, below the settings
+//!     * `NRF52_SETTINGS_SIZE`: settings pages at the end of FLASH
+//!     * `NRF52_RAM_RESERVED_START`: start of RAM that is used by a SoftDevice
+//!
+//! The last `UNINIT_SIZE` bytes of RAM are the `UNINIT` region for the crash record, see
+//! `src/crash.rs`. It is at the same address in every image built for the chip, so a
+//! bootloader and its application share it.
+//!
+//! `NRF52_MEMORY_X` is the absolute path of a `memory.x` that is used as is instead, e.g.
+//! one that is shipped with the final binary. It must define `UNINIT` too.
+//!
+//! `heap.x` holds the default heap size, RAM is only reserved for the heap with the
+//! `alloc` feature.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+const FLASH_ORIGIN: u32 = 0x0000_0000;
+const RAM_ORIGIN: u32 = 0x2000_0000;
+
+/// FLASH is erased in pages of 4K
+const PAGE_SIZE: u32 = 0x1000;
+
+/// Default heap size with the `alloc` feature
+const HEAP_SIZE: u32 = 0x2000;
+
+/// Smallest RAM region that is left for `.data`, `.bss`, the heap and the stack
+const MIN_RAM: u32 = 0x2000;
+
+/// Size of the `UNINIT` region at the end of RAM
+const UNINIT_SIZE: u32 = 0x100;
+
+const ENV_VARS: [&str; 5] = [
+    "NRF52_FLASH_RESERVED_START",
+    "NRF52_BOOTLOADER_SIZE",
+    "NRF52_SETTINGS_SIZE",
+    "NRF52_RAM_RESERVED_START",
+    "NRF52_MEMORY_X",
+];
+
+/// FLASH and RAM size of a chip variant
+struct Chip {
+    name: &'static str,
+    flash: u32,
+    ram: u32,
+}
+
+static CHIPS: [Chip; 3] = [
+    Chip {
+        name: "nrf52832-qfaa",
+        flash: 512 * 1024,
+        ram: 64 * 1024,
+    },
+    Chip {
+        name: "nrf52832-qfab",
+        flash: 256 * 1024,
+        ram: 32 * 1024,
+    },
+    Chip {
+        name: "nrf52840",
+        flash: 1024 * 1024,
+        ram: 256 * 1024,
+    },
+];
+
+fn main() {
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=build.rs");
+    for var in ENV_VARS.iter() {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+
+    let heap_size = if env::var_os("CARGO_FEATURE_ALLOC").is_some() {
+        HEAP_SIZE
+    } else {
+        0
+    };
+    let mut f = File::create(out.join("heap.x")).unwrap();
+    writeln!(
+        f,
+        "__heap_size__ = DEFINED(__heap_size__) ? __heap_size__ : {:#x};",
+        heap_size
+    )
+    .unwrap();
+
+    if let Some(user_memory) = env::var_os("NRF52_MEMORY_X") {
+        let user_memory = PathBuf::from(user_memory);
+        if !user_memory.is_absolute() || !user_memory.is_file() {
+            panic!(
+                "NRF52_MEMORY_X ({}) must be the absolute path of a file",
+                user_memory.display()
+            );
+        }
+        println!("cargo:rerun-if-changed={}", user_memory.display());
+        fs::copy(&user_memory, out.join("memory.x")).unwrap();
+        return;
+    }
+
+    let chip = chip();
+    let flash_start = env_size("NRF52_FLASH_RESERVED_START");
+    let bootloader = env_size("NRF52_BOOTLOADER_SIZE");
+    let settings = env_size("NRF52_SETTINGS_SIZE");
+    let ram_start = env_size("NRF52_RAM_RESERVED_START");
+
+    for &(var, size) in [
+        ("NRF52_FLASH_RESERVED_START", flash_start),
+        ("NRF52_BOOTLOADER_SIZE", bootloader),
+        ("NRF52_SETTINGS_SIZE", settings),
+    ]
+    .iter()
+    {
+        if size % PAGE_SIZE != 0 {
+            panic!("{} ({:#x}) must be a multiple of the page size", var, size);
+        }
+    }
+    if ram_start % 8 != 0 {
+        panic!(
+            "NRF52_RAM_RESERVED_START ({:#x}) must be 8 byte aligned",
+            ram_start
+        );
+    }
+
+    let reserved = flash_start
+        .checked_add(bootloader)
+        .and_then(|reserved| reserved.checked_add(settings))
+        .unwrap_or_else(|| {
+            panic!(
+                "NRF52_FLASH_RESERVED_START + NRF52_BOOTLOADER_SIZE + NRF52_SETTINGS_SIZE \
+                 overflows"
+            )
+        });
+    if reserved >= chip.flash {
+        panic!(
+            "{:#x} bytes of FLASH are reserved, {} only has {:#x}",
+            reserved, chip.name, chip.flash
+        );
+    }
+    if ram_start > chip.ram - UNINIT_SIZE - MIN_RAM {
+        panic!(
+            "{:#x} bytes of RAM are reserved, {} only has {:#x}",
+            ram_start, chip.name, chip.ram
+        );
+    }
+
+    let mut f = File::create(out.join("memory.x")).unwrap();
+    writeln!(f, "/* Generated by build.rs for {} */", chip.name).unwrap();
+    writeln!(f, "MEMORY {{").unwrap();
+    writeln!(
+        f,
+        "    FLASH  (rx)  : ORIGIN = {:#010x}, LENGTH = {:#x}",
+        FLASH_ORIGIN + flash_start,
+        chip.flash - reserved
+    )
+    .unwrap();
+    writeln!(
+        f,
+        "    RAM    (rwx) : ORIGIN = {:#010x}, LENGTH = {:#x}",
+        RAM_ORIGIN + ram_start,
+        chip.ram - UNINIT_SIZE - ram_start
+    )
+    .unwrap();
+    writeln!(
+        f,
+        "    UNINIT (rw)  : ORIGIN = {:#010x}, LENGTH = {:#x}",
+        RAM_ORIGIN + chip.ram - UNINIT_SIZE,
+        UNINIT_SIZE
+    )
+    .unwrap();
+    writeln!(f, "}}").unwrap();
+}
+
+/// Chip variant that is selected by a feature, `nrf52832-qfaa` by default
+fn chip() -> &'static Chip {
+    let selected: Vec<&Chip> = CHIPS
+        .iter()
+        .filter(|chip| {
+            let feature = chip.name.to_uppercase().replace('-', "_");
+            env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some()
+        })
+        .collect();
+
+    match selected.len() {
+        0 => &CHIPS[0],
+        1 => selected[0],
+        _ => panic!("only one chip variant feature can be enabled"),
+    }
+}
+
+/// Parse a size from the environment, 0 if the variable isn't set
+fn env_size(var: &str) -> u32 {
+    let value = match env::var(var) {
+        Ok(value) => value,
+        Err(_) => return 0,
+    };
+    let value = value.trim();
+
+    let (digits, scale) = if value.ends_with('K') || value.ends_with('k') {
+        (&value[..value.len() - 1], 1024)
+    } else {
+        (value, 1)
+    };
+    let size = if digits.starts_with("0x") || digits.starts_with("0X") {
+        u32::from_str_radix(&digits[2..], 16)
+    } else {
+        digits.parse()
+    };
+
+    match size {
+        Ok(size) => size
+            .checked_mul(scale)
+            .unwrap_or_else(|| panic!("{} ({}) is too large", var, value)),
+        Err(_) => panic!("{} ({}) is not a valid size", var, value),
+    }
+}
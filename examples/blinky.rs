@@ -2,27 +2,22 @@
 
 extern crate nrf52dk_rs;
 
-use nrf52dk_rs::board::LEDS;
-use nrf52dk_rs::peripherals::gpio::GPIO;
+use nrf52dk_rs::Peripherals;
 
 fn main() {
-    let gpio = &GPIO;
+    let p = Peripherals::take().unwrap();
+    let systick = p.SYSTICK;
 
     // configure LEDs
-    for led in LEDS.iter() {
-        unsafe {
-            gpio.make_output(*led);
-        }
+    let mut leds = [p.P0.p0_17, p.P0.p0_18, p.P0.p0_19, p.P0.p0_20];
+    for led in leds.iter_mut() {
+        led.make_output();
     }
 
     loop {
-        for led in LEDS.iter() {
-            unsafe { gpio.toggle(*led) }
-            dummy_sleep();
+        for led in leds.iter_mut() {
+            led.toggle();
+            systick.delay_ms(250);
         }
     }
 }
-
-fn dummy_sleep() {
-    for _ in 0..10000 {}
-}
@@ -4,16 +4,23 @@
 
 extern crate nrf52dk_rs;
 
+use nrf52dk_rs::peripherals::uart::UartPins;
+use nrf52dk_rs::Peripherals;
+
 #[link_section = ".data"]
 static BUFFER: [u8; 5] = [0x48, 0x45, 0x4c, 0x4c, 0x4f];
 
 const BAUDRAUTE: u32 = 115200;
 
 fn main() {
-    unsafe {
-        let uart = &nrf52dk_rs::peripherals::uart::UART;
-        uart.initialize(BAUDRAUTE);
-        uart.transmit(&BUFFER);
-    }
+    let p = Peripherals::take().unwrap();
+    let pins = UartPins {
+        txd: p.P0.p0_06,
+        rxd: p.P0.p0_08,
+        cts: p.P0.p0_07,
+        rts: p.P0.p0_05,
+    };
+    let mut uart = p.UARTE0.configure(pins, BAUDRAUTE);
+    uart.lock(|uart| uart.transmit(&BUFFER));
     loop {}
 }
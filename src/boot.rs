@@ -0,0 +1,147 @@
+//! Boot information
+//!
+//! `reset_handler` reads and clears RESETREAS and GPREGRET before `main` is called so
+//! the next reset starts from a clean state. The captured values are available to the
+//! application through `info()`.
+//!
+//! A bootloader linked at the start of FLASH starts an application that is built with
+//! `NRF52_FLASH_RESERVED_START=<offset>`, see `build.rs`, by `jump_to_application`. With
+//! the `bootloader` feature RESETREAS, GPREGRET and the crash record aren't consumed at
+//! reset, so the application sees the real values. The crash record is in the `UNINIT`
+//! region at the end of RAM, which is at the same address for both images as long as
+//! they are built for the same chip variant:
+//!
+//! ```ignore
+//! if boot::info().mode != BootMode::Bootloader {
+//!     let err = unsafe { boot::jump_to_application(0x1_0000) };
+//!     writeln!(console, "no application: {:?}", err);
+//! }
+//! ```
+
+use cortex_m::peripheral::{MPU, NVIC, SCB, SYST};
+use peripherals::power::{BootMode, ResetReason, POWER};
+
+/// Factory information configuration registers
+const FICR_CODEPAGESIZE: *const u32 = 0x1000_0010 as *const u32;
+const FICR_CODESIZE: *const u32 = 0x1000_0014 as *const u32;
+const FICR_INFO_RAM: *const u32 = 0x1000_010C as *const u32;
+
+const RAM_START: u32 = 0x2000_0000;
+
+const ICSR_PENDSVCLR: u32 = 1 << 27;
+const ICSR_PENDSTCLR: u32 = 1 << 25;
+
+/// Why `jump_to_application` didn't start the application
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JumpError {
+    /// The vector table isn't page aligned or outside of FLASH
+    InvalidAddress,
+    /// The application isn't programmed, i.e. the vector table is erased
+    Erased,
+    /// The initial stack pointer isn't an aligned address in RAM
+    InvalidStackPointer,
+    /// The reset vector isn't a Thumb address in FLASH
+    InvalidResetVector,
+}
+
+/// Why and how the system was started
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BootInfo {
+    /// Decoded RESETREAS
+    pub reset_reason: ResetReason,
+    /// Boot mode retained in GPREGRET, see `Power::reset_into`
+    pub mode: BootMode,
+}
+
+static mut BOOT_INFO: Option<BootInfo> = None;
+
+/// Capture the reset reason and boot mode, called once from `reset_handler`
+///
+/// With the `bootloader` feature both are left in place for the application
+pub(crate) unsafe fn capture() {
+    let (reset_reason, mode) = POWER.lock(|power| {
+        let mode = BootMode::from(power.gpregret());
+        if cfg!(feature = "bootloader") {
+            (power.reset_reason(), mode)
+        } else {
+            power.set_gpregret(BootMode::Normal.bits());
+            (power.take_reset_reason(), mode)
+        }
+    });
+
+    BOOT_INFO = Some(BootInfo { reset_reason, mode });
+}
+
+/// Reset reason and boot mode of the current boot
+pub fn info() -> BootInfo {
+    // Only written by `reset_handler` before `main` is called
+    unsafe { BOOT_INFO.expect("boot info is captured in reset_handler") }
+}
+
+/// Start the application whose vector table is at `addr`
+///
+/// The vector table is validated, interrupts and SysTick are disabled, the MPU is turned
+/// off and VTOR, MSP and CONTROL are set up like after a reset before branching to the
+/// reset vector. The UART, GPIOTE and the clocks are stopped, other peripherals that the
+/// bootloader uses directly must be stopped first. Only returns if the validation failed.
+///
+/// Must be called from thread mode and outside of any `Peripherals` lock
+pub unsafe fn jump_to_application(addr: u32) -> JumpError {
+    let page_size = FICR_CODEPAGESIZE.read_volatile();
+    let flash_size = page_size * FICR_CODESIZE.read_volatile();
+    let ram_end = RAM_START + FICR_INFO_RAM.read_volatile() * 1024;
+
+    if addr % page_size != 0 || addr >= flash_size {
+        return JumpError::InvalidAddress;
+    }
+
+    let vectors = addr as *const u32;
+    let sp = vectors.read_volatile();
+    let reset = vectors.offset(1).read_volatile();
+
+    if sp == 0xFFFF_FFFF && reset == 0xFFFF_FFFF {
+        return JumpError::Erased;
+    }
+    if sp % 8 != 0 || sp <= RAM_START || sp > ram_end {
+        return JumpError::InvalidStackPointer;
+    }
+    if reset & 1 == 0 || reset <= addr || reset >= flash_size {
+        return JumpError::InvalidResetVector;
+    }
+
+    ::cortex_m::interrupt::disable();
+    ::peripherals::reset();
+
+    let nvic = &*NVIC::ptr();
+    for (icer, icpr) in nvic.icer.iter().zip(nvic.icpr.iter()) {
+        icer.write(0xFFFF_FFFF);
+        icpr.write(0xFFFF_FFFF);
+    }
+
+    let syst = &*SYST::ptr();
+    syst.csr.write(0);
+    syst.rvr.write(0);
+    syst.cvr.write(0);
+
+    // The stack guard of the bootloader would fault the application
+    (*MPU::ptr()).ctrl.write(0);
+
+    let scb = &*SCB::ptr();
+    scb.icsr.write(ICSR_PENDSVCLR | ICSR_PENDSTCLR);
+    scb.vtor.write(addr);
+
+    ::cortex_m::asm::dsb();
+    ::cortex_m::asm::isb();
+
+    // Privileged, MSP and no FPU context, i.e. CONTROL after reset. Nothing is pushed
+    // after switching the stack pointer
+    asm!("
+        msr control, $2
+        isb
+        msr msp, $0
+        cpsie i
+        bx $1
+    " :: "r"(sp), "r"(reset), "r"(0) :: "volatile");
+
+    unreachable!()
+}
@@ -0,0 +1,51 @@
+//! Console on the UART
+//!
+//! `Uart::transmit` only accepts buffers in RAM because of EasyDMA, so the console copies
+//! everything through a bounce buffer first. This makes it usable from any context, e.g.
+//! fault handlers printing strings that are stored in flash.
+
+use core::fmt;
+use cortex_m::interrupt;
+use peripherals::uart::Uart;
+
+/// Baudrate of the console
+pub const BAUDRATE: u32 = 115_200;
+
+const BOUNCE_BUFFER_SIZE: usize = 64;
+
+static mut BOUNCE_BUFFER: [u8; BOUNCE_BUFFER_SIZE] = [0; BOUNCE_BUFFER_SIZE];
+
+/// Console writer
+pub struct Console {
+    uart: Uart,
+}
+
+impl Console {
+    /// Create a console and (re-)initialize the UART with `BAUDRATE`
+    ///
+    /// This doesn't go through the `UART` singleton so it can be used from fault handlers
+    pub unsafe fn new() -> Self {
+        let uart = Uart::new();
+        uart.initialize(BAUDRATE);
+        Console { uart }
+    }
+
+    /// Write raw bytes
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        interrupt::free(|_| {
+            for chunk in bytes.chunks(BOUNCE_BUFFER_SIZE) {
+                unsafe {
+                    BOUNCE_BUFFER[..chunk.len()].copy_from_slice(chunk);
+                    self.uart.transmit(&BOUNCE_BUFFER[..chunk.len()]);
+                }
+            }
+        });
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Crash records that survive a reset
+//!
+//! The panic handler and the fault handlers store a record of the crash in the `.uninit`
+//! section, which is neither initialized nor cleared by `reset_handler`. It is placed in
+//! the `UNINIT` region at the end of RAM, see `build.rs`, so its address doesn't move with
+//! `.bss` and a bootloader and its application find it at the same place. The record is
+//! checksummed so garbage after a power-on reset isn't mistaken for a crash. At the next
+//! boot the record is moved to `previous()` where the application can log it:
+//!
+//! ```ignore
+//! if let Some(crash) = crash::previous() {
+//!     writeln!(console, "previous crash: {}", crash);
+//! }
+//! ```
+
+use core::fmt::{self, Write};
+use core::{mem, ptr, slice, str};
+use fault::{FaultKind, FaultReport};
+
+/// Maximum length of the message in a record
+pub const MESSAGE_SIZE: usize = 128;
+
+/// A record that hasn't been reported by `previous` yet
+const PENDING_MAGIC: u32 = 0xC7A5_4ED1;
+/// A record that has been read at boot, only `reset_count` is kept
+const CONSUMED_MAGIC: u32 = 0xC7A5_4ED0;
+
+/// Index of `CrashRecord::checksum` in words
+const CHECKSUM_WORD: usize = 1;
+
+/// Crash record in `.uninit`
+#[link_section = ".uninit.CRASH_RECORD"]
+static mut CRASH_RECORD: CrashRecord = CrashRecord::empty(0);
+
+/// Crash that happened before the last reset
+static mut PREVIOUS: Option<CrashRecord> = None;
+
+/// What caused the crash
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashKind {
+    Panic,
+    Fault(FaultKind),
+    /// The record was written by an incompatible version
+    Unknown,
+}
+
+/// Crash record
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    magic: u32,
+    checksum: u32,
+    /// Number of crashes since power-on, including this one
+    pub reset_count: u32,
+    kind: u32,
+    /// Program counter, of the panic handler for panics
+    pub pc: u32,
+    /// Link register
+    pub lr: u32,
+    /// Configurable Fault Status Register
+    pub cfsr: u32,
+    /// HardFault Status Register
+    pub hfsr: u32,
+    /// MemManage Fault Address Register
+    pub mmfar: u32,
+    /// BusFault Address Register
+    pub bfar: u32,
+    message_len: u32,
+    message: [u8; MESSAGE_SIZE],
+}
+
+impl CrashRecord {
+    const fn empty(reset_count: u32) -> Self {
+        CrashRecord {
+            magic: 0,
+            checksum: 0,
+            reset_count,
+            kind: 0,
+            pc: 0,
+            lr: 0,
+            cfsr: 0,
+            hfsr: 0,
+            mmfar: 0,
+            bfar: 0,
+            message_len: 0,
+            message: [0; MESSAGE_SIZE],
+        }
+    }
+
+    /// What caused the crash
+    pub fn kind(&self) -> CrashKind {
+        match self.kind {
+            0 => CrashKind::Panic,
+            1 => CrashKind::Fault(FaultKind::HardFault),
+            2 => CrashKind::Fault(FaultKind::MemManage),
+            3 => CrashKind::Fault(FaultKind::BusFault),
+            4 => CrashKind::Fault(FaultKind::UsageFault),
+            _ => CrashKind::Unknown,
+        }
+    }
+
+    /// Panic message and location or the decoded fault, truncated to `MESSAGE_SIZE`
+    pub fn message(&self) -> &str {
+        let len = (self.message_len as usize).min(MESSAGE_SIZE);
+        // Truncation might have split a character
+        match str::from_utf8(&self.message[..len]) {
+            Ok(message) => message,
+            Err(e) => unsafe { str::from_utf8_unchecked(&self.message[..e.valid_up_to()]) },
+        }
+    }
+
+    fn words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const Self as *const u32,
+                mem::size_of::<Self>() / 4,
+            )
+        }
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        self.words()
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != CHECKSUM_WORD)
+            .fold(0x811C_9DC5, |acc, (_, &word)| {
+                (acc ^ word).wrapping_mul(0x0100_0193)
+            })
+    }
+
+    fn is_valid(&self) -> bool {
+        (self.magic == PENDING_MAGIC || self.magic == CONSUMED_MAGIC)
+            && self.checksum == self.compute_checksum()
+    }
+
+    fn seal(&mut self, magic: u32) {
+        self.magic = magic;
+        self.checksum = self.compute_checksum();
+    }
+}
+
+impl fmt::Write for CrashRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.message_len as usize;
+        let len = s.len().min(MESSAGE_SIZE - start);
+        self.message[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.message_len += len as u32;
+        Ok(())
+    }
+}
+
+impl fmt::Display for CrashRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?} (crash {}): {}",
+            self.kind(),
+            self.reset_count,
+            self.message()
+        )?;
+        writeln!(f, "pc: {:#010x} lr: {:#010x}", self.pc, self.lr)?;
+        writeln!(
+            f,
+            "CFSR: {:#010x} HFSR: {:#010x} MMFAR: {:#010x} BFAR: {:#010x}",
+            self.cfsr, self.hfsr, self.mmfar, self.bfar
+        )
+    }
+}
+
+/// Crash that happened before the last reset, if any
+pub fn previous() -> Option<CrashRecord> {
+    unsafe { PREVIOUS }
+}
+
+/// Number of crashes since power-on
+pub fn reset_count() -> u32 {
+    unsafe { ptr::read_volatile(&CRASH_RECORD).reset_count }
+}
+
+/// Forget the previous crash and reset the crash counter
+pub fn clear() {
+    unsafe {
+        PREVIOUS = None;
+        let mut record = CrashRecord::empty(0);
+        record.seal(CONSUMED_MAGIC);
+        ptr::write_volatile(&mut CRASH_RECORD, record);
+    }
+}
+
+/// Take the record of the previous crash, called from `reset_handler`
+pub(crate) unsafe fn capture() {
+    let record = ptr::read_volatile(&CRASH_RECORD);
+    if !record.is_valid() {
+        // Power-on reset, the content of RAM is undefined
+        clear();
+        return;
+    }
+
+    if record.magic == PENDING_MAGIC {
+        PREVIOUS = Some(record);
+    }
+    // A bootloader leaves the record to the application it starts, both find it in the
+    // `UNINIT` region
+    if cfg!(feature = "bootloader") {
+        return;
+    }
+    let mut consumed = CrashRecord::empty(record.reset_count);
+    consumed.seal(CONSUMED_MAGIC);
+    ptr::write_volatile(&mut CRASH_RECORD, consumed);
+}
+
+/// Store a record of a panic
+pub(crate) unsafe fn record_panic(info: &::core::panic::PanicInfo) {
+    let mut record = CrashRecord::empty(reset_count().wrapping_add(1));
+    record.pc = ::cortex_m::register::pc::read();
+    record.lr = ::cortex_m::register::lr::read();
+    let _ = write!(record, "{}", info);
+    record.seal(PENDING_MAGIC);
+    ptr::write_volatile(&mut CRASH_RECORD, record);
+}
+
+/// Store a record of a fault
+pub(crate) unsafe fn record_fault(report: &FaultReport) {
+    let mut record = CrashRecord::empty(reset_count().wrapping_add(1));
+    record.kind = report.kind as u32 + 1;
+    record.pc = report.frame.pc;
+    record.lr = report.frame.lr;
+    record.cfsr = report.cfsr;
+    record.hfsr = report.hfsr;
+    record.mmfar = report.mmfar;
+    record.bfar = report.bfar;
+    let _ = record.write_str(report.cause().unwrap_or("unknown cause"));
+    record.seal(PENDING_MAGIC);
+    ptr::write_volatile(&mut CRASH_RECORD, record);
+}
@@ -0,0 +1,66 @@
+//! Critical sections and a `Mutex` for data that is shared with interrupt handlers
+//!
+//! The drivers behind the `Peripherals` handles are wrapped in a `Mutex`. They can only be
+//! reached through `Mutex::lock`, which runs with interrupts masked, so `main` and
+//! interrupt handlers never touch the same registers at the same time:
+//!
+//! ```ignore
+//! static COUNTER: Mutex<u32> = Mutex::new(0);
+//!
+//! COUNTER.lock(|counter| *counter += 1);
+//! ```
+
+use core::cell::{Cell, UnsafeCell};
+use cortex_m::interrupt;
+
+pub use cortex_m::interrupt::CriticalSection;
+
+/// Run `f` with interrupts masked
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(&CriticalSection) -> R,
+{
+    interrupt::free(f)
+}
+
+/// Mutual exclusion between `main` and interrupt handlers, equivalent to
+/// `cortex_m::interrupt::Mutex<RefCell<T>>`
+pub struct Mutex<T> {
+    locked: Cell<bool>,
+    data: UnsafeCell<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Constructor
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            locked: Cell::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Run `f` with exclusive access to the data in a critical section
+    ///
+    /// Panics if the mutex already is locked by an enclosing `lock`
+    pub fn lock<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        interrupt::free(|_| {
+            assert!(!self.locked.replace(true), "mutex already locked");
+            let ret = f(unsafe { &mut *self.data.get() });
+            self.locked.set(false);
+            ret
+        })
+    }
+
+    /// Access the data without locking
+    ///
+    /// Only for contexts that never return to the code that holds the lock, e.g. the
+    /// panic and fault handlers
+    pub unsafe fn steal(&self) -> &mut T {
+        &mut *self.data.get()
+    }
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
@@ -7,6 +7,18 @@ pub unsafe fn init_data(mut edata: *mut u32, mut sdata: *mut u32, sdata_end: *mu
     }
 }
 
+// Call every function in the array of function pointers `start..end`, e.g. the static
+// constructors in `.preinit_array` and `.init_array`
+pub unsafe fn run_init_array(
+    mut start: *const unsafe extern "C" fn(),
+    end: *const unsafe extern "C" fn(),
+) {
+    while start < end {
+        (start.read())();
+        start = start.offset(1);
+    }
+}
+
 // Clear the `bss segment` (non initialized data)
 pub unsafe fn clear_bss(mut bdata: *mut u32, bdata_end: *mut u32) {
     while bdata < bdata_end {
@@ -14,3 +26,13 @@ pub unsafe fn clear_bss(mut bdata: *mut u32, bdata_end: *mut u32) {
         bdata = bdata.offset(1);
     }
 }
+
+// Fill the stack below the current stack pointer with `pattern`, down to `limit`
+#[cfg(feature = "stack-paint")]
+pub unsafe fn paint_stack(mut limit: *mut u32, pattern: u32) {
+    let sp = ::cortex_m::register::msp::read() as *mut u32;
+    while limit < sp {
+        limit.write_volatile(pattern);
+        limit = limit.offset(1);
+    }
+}
@@ -0,0 +1,146 @@
+//! Static no-alloc executor
+//!
+//! Drivers complete futures from their interrupt handlers by waking the `Waker` that was
+//! registered in a `WakerSlot`. The executor only polls futures that have been woken and
+//! sleeps with `wfe` when there is nothing to do. `Uart::write`, `Timer::after`,
+//! `Gpiote::wait_for` and `clock::request_hfxo_async` return such futures.
+//!
+//! `async` blocks need the 2018 edition, so the example is written from the point of view
+//! of an application crate:
+//!
+//! ```ignore
+//! let timer = Timer::start(1000);
+//! let mut task = async {
+//!     loop {
+//!         uart.lock(|uart| uart.write(&BUFFER)).await;
+//!         timer.after(ms(10)).await;
+//!     }
+//! };
+//! executor::block_on(task);
+//! ```
+
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use cortex_m::{asm, interrupt};
+
+mod timer;
+
+pub use self::timer::{ms, secs, Delay, Duration, Timer};
+
+/// Maximum number of futures that `run` can drive
+pub const MAX_TASKS: usize = 8;
+
+/// Woken tasks, one bit per task
+static WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+static VTABLE: RawWakerVTable =
+    RawWakerVTable::new(waker_clone, waker_wake, waker_wake, waker_drop);
+
+unsafe fn waker_clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn waker_wake(data: *const ()) {
+    WOKEN.fetch_or(1 << data as usize, Ordering::SeqCst);
+    // Make sure a `wfe` that races with the wakeup returns
+    asm::sev();
+}
+
+unsafe fn waker_drop(_: *const ()) {}
+
+fn waker(task: usize) -> Waker {
+    unsafe { Waker::from_raw(RawWaker::new(task as *const (), &VTABLE)) }
+}
+
+/// Run `future` to completion
+///
+/// Must not be nested in another `block_on` or `run`
+pub fn block_on<F: Future>(mut future: F) -> F::Output {
+    // `future` is shadowed and can't be moved again
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    let waker = waker(0);
+    let mut cx = Context::from_waker(&waker);
+
+    WOKEN.store(1, Ordering::SeqCst);
+    loop {
+        if WOKEN.swap(0, Ordering::SeqCst) & 1 != 0 {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        } else {
+            asm::wfe();
+        }
+    }
+}
+
+/// Run all `tasks` until every one of them has completed
+///
+/// Must not be nested in another `block_on` or `run`
+pub fn run(tasks: &mut [Pin<&mut dyn Future<Output = ()>>]) {
+    assert!(tasks.len() <= MAX_TASKS, "too many tasks");
+    let mut pending = (1 << tasks.len()) - 1;
+
+    // Poll every task once
+    WOKEN.store(pending, Ordering::SeqCst);
+    while pending != 0 {
+        let woken = WOKEN.swap(0, Ordering::SeqCst) & pending;
+        if woken == 0 {
+            asm::wfe();
+            continue;
+        }
+
+        for (i, task) in tasks.iter_mut().enumerate() {
+            if woken & (1 << i) == 0 {
+                continue;
+            }
+            let waker = waker(i);
+            let mut cx = Context::from_waker(&waker);
+            if task.as_mut().poll(&mut cx).is_ready() {
+                pending &= !(1 << i);
+            }
+        }
+    }
+}
+
+/// Storage for the waker of a future that is completed from an interrupt handler
+pub struct WakerSlot {
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+impl WakerSlot {
+    /// Empty slot
+    pub const fn new() -> Self {
+        WakerSlot {
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Store `waker`, replacing the previous waker
+    pub fn register(&self, waker: &Waker) {
+        interrupt::free(|_| {
+            let slot = unsafe { &mut *self.waker.get() };
+            match *slot {
+                Some(ref current) if current.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        });
+    }
+
+    /// Remove the stored waker without waking it
+    pub fn clear(&self) {
+        interrupt::free(|_| unsafe { *self.waker.get() = None });
+    }
+
+    /// Wake and remove the stored waker, if any
+    pub fn wake(&self) {
+        let waker = interrupt::free(|_| unsafe { (*self.waker.get()).take() });
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+unsafe impl Sync for WakerSlot {}
@@ -0,0 +1,138 @@
+//! Timer futures driven by the SysTick tick
+
+use super::WakerSlot;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use cortex_m::interrupt;
+use peripherals::systick::SYSTICK;
+
+/// Number of `Delay`s that can wait at the same time, others fall back to polling
+const MAX_TIMERS: usize = 8;
+
+/// Deadline in ticks of every timer slot, `None` when the slot is free
+static mut DEADLINES: [Option<u32>; MAX_TIMERS] = [None; MAX_TIMERS];
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static WAKERS: [WakerSlot; MAX_TIMERS] = [
+    WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(),
+    WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(),
+];
+
+/// Span of time with millisecond resolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Duration {
+    ms: u32,
+}
+
+impl Duration {
+    /// Duration in milliseconds
+    pub fn as_millis(&self) -> u32 {
+        self.ms
+    }
+}
+
+/// `ms` milliseconds
+pub fn ms(ms: u32) -> Duration {
+    Duration { ms }
+}
+
+/// `secs` seconds
+pub fn secs(secs: u32) -> Duration {
+    Duration { ms: secs * 1000 }
+}
+
+/// Timer based on the SysTick tick
+pub struct Timer {
+    _private: (),
+}
+
+impl Timer {
+    /// Start SysTick at `tick_hz`, which is the resolution of the timer
+    ///
+    /// A tick that already runs, e.g. for the kernel, is shared and keeps its tick rate
+    pub fn start(tick_hz: u32) -> Self {
+        SYSTICK.add_tick_hook(on_tick);
+        if SYSTICK.tick_hz() == 0 {
+            unsafe { SYSTICK.start(tick_hz) };
+        }
+        Timer { _private: () }
+    }
+
+    /// Future that completes after `duration`
+    pub fn after(&self, duration: Duration) -> Delay {
+        let ticks = (u64::from(duration.ms) * u64::from(SYSTICK.tick_hz()) + 999) / 1000;
+        Delay {
+            deadline: SYSTICK.ticks().wrapping_add(ticks.max(1) as u32),
+            slot: None,
+        }
+    }
+}
+
+/// Future returned by `Timer::after`
+pub struct Delay {
+    deadline: u32,
+    slot: Option<usize>,
+}
+
+impl Delay {
+    fn expired(&self) -> bool {
+        SYSTICK.ticks().wrapping_sub(self.deadline) as i32 >= 0
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.expired() {
+            return Poll::Ready(());
+        }
+
+        if self.slot.is_none() {
+            let deadline = self.deadline;
+            self.slot = interrupt::free(|_| unsafe {
+                let slot = DEADLINES.iter().position(|d| d.is_none());
+                if let Some(slot) = slot {
+                    DEADLINES[slot] = Some(deadline);
+                }
+                slot
+            });
+        }
+
+        match self.slot {
+            Some(slot) => WAKERS[slot].register(cx.waker()),
+            // Every slot is in use, poll again
+            None => cx.waker().wake_by_ref(),
+        }
+
+        // The deadline might have passed before the waker was registered
+        if self.expired() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            interrupt::free(|_| unsafe { DEADLINES[slot] = None });
+            WAKERS[slot].clear();
+        }
+    }
+}
+
+/// Wake expired timers, called from the SysTick handler
+fn on_tick() {
+    let now = SYSTICK.ticks();
+    for (slot, waker) in WAKERS.iter().enumerate() {
+        let expired = interrupt::free(|_| unsafe {
+            DEADLINES[slot].map_or(false, |deadline| now.wrapping_sub(deadline) as i32 >= 0)
+        });
+        if expired {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Fault handlers
+//!
+//! The fault handlers capture the exception frame that was stacked on entry together
+//! with the fault status registers and print a decoded report on the console UART. The
+//! report is also stored in `FAULT_REPORT` so it can be inspected from a debugger:
+//!
+//! ```text
+//! (gdb) p FAULT_REPORT
+//! ```
+//!
+//! MemManage, BusFault and UsageFault are enabled in SHCSR at reset so they are reported
+//! as such instead of escalating to HardFault. Trapping on divide by zero and unaligned
+//! accesses is opt-in via the `div0-trap` and `unaligned-trap` features. Note, Rust
+//! already checks integer division so `div0-trap` mostly matters for linked C code.
+//!
+//! Faults caused by the main stack running into the guard set up by `stack` are
+//! reported as stack overflows. The handler then continues on the top of the main stack.
+//!
+//! Every fault is stored as a crash record, see `crash`. The chip is reset afterwards if
+//! the `fault-reset` feature is enabled, otherwise it halts.
+
+use console::Console;
+use core::fmt::{self, Write};
+use cortex_m::peripheral::{DCB, SCB};
+use crash;
+use stack;
+
+const DHCSR_C_DEBUGEN: u32 = 1 << 0;
+
+const SHCSR_MEMFAULTENA: u32 = 1 << 16;
+const SHCSR_BUSFAULTENA: u32 = 1 << 17;
+const SHCSR_USGFAULTENA: u32 = 1 << 18;
+
+#[cfg(feature = "unaligned-trap")]
+const CCR_UNALIGN_TRP: u32 = 1 << 3;
+#[cfg(feature = "div0-trap")]
+const CCR_DIV_0_TRP: u32 = 1 << 4;
+
+const CFSR_MMFSR_MASK: u32 = 0x0000_00FF;
+const CFSR_BFSR_MASK: u32 = 0x0000_FF00;
+const CFSR_UFSR_MASK: u32 = 0xFFFF_0000;
+
+/// CFSR bits and their meaning
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const CFSR_CAUSES: [(u32, &str); 17] = [
+    // MemManage
+    (1 << 0, "instruction access violation"),
+    (1 << 1, "data access violation"),
+    (1 << 3, "MemManage fault on unstacking"),
+    (1 << 4, "MemManage fault on stacking"),
+    (1 << 5, "MemManage fault during FP lazy state preservation"),
+    // BusFault
+    (1 << 8, "instruction bus error"),
+    (1 << 9, "precise data bus error"),
+    (1 << 10, "imprecise data bus error"),
+    (1 << 11, "BusFault on unstacking"),
+    (1 << 12, "BusFault on stacking"),
+    (1 << 13, "BusFault during FP lazy state preservation"),
+    // UsageFault
+    (1 << 16, "undefined instruction"),
+    (1 << 17, "invalid state, e.g. branch to ARM state"),
+    (1 << 18, "invalid PC load by EXC_RETURN"),
+    (1 << 19, "no coprocessor, e.g. FPU disabled"),
+    (1 << 24, "unaligned access"),
+    (1 << 25, "divide by zero"),
+];
+
+const CFSR_MSTKERR: u32 = 1 << 4;
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BSTKERR: u32 = 1 << 12;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+const HFSR_VECTTBL: u32 = 1 << 1;
+const HFSR_FORCED: u32 = 1 << 30;
+const HFSR_DEBUGEVT: u32 = 1 << 31;
+
+/// Registers stacked by the processor on exception entry
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+/// Fault exception that was taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    HardFault = 0,
+    MemManage = 1,
+    BusFault = 2,
+    UsageFault = 3,
+}
+
+impl FaultKind {
+    /// CFSR bits that belong to this fault
+    fn cfsr_mask(&self) -> u32 {
+        match *self {
+            FaultKind::HardFault => !0,
+            FaultKind::MemManage => CFSR_MMFSR_MASK,
+            FaultKind::BusFault => CFSR_BFSR_MASK,
+            FaultKind::UsageFault => CFSR_UFSR_MASK,
+        }
+    }
+}
+
+/// Stack that was active when the fault occurred
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stack {
+    /// Main stack pointer
+    Main,
+    /// Process stack pointer
+    Process,
+}
+
+/// Decoded fault
+#[derive(Clone, Copy, Debug)]
+pub struct FaultReport {
+    /// Fault exception that was taken
+    pub kind: FaultKind,
+    /// Stack that the exception frame was pushed to
+    pub stack: Stack,
+    /// Stack pointer when the fault was taken
+    pub sp: u32,
+    /// Stacked registers, not valid if stacking faulted
+    pub frame: ExceptionFrame,
+    /// Configurable Fault Status Register
+    pub cfsr: u32,
+    /// HardFault Status Register
+    pub hfsr: u32,
+    /// MemManage Fault Address Register
+    pub mmfar: u32,
+    /// BusFault Address Register
+    pub bfar: u32,
+}
+
+impl FaultReport {
+    /// Read the fault status registers
+    pub unsafe fn capture(kind: FaultKind, stack: Stack, frame: &ExceptionFrame) -> Self {
+        let scb = &*SCB::ptr();
+        let sp = frame as *const ExceptionFrame as u32;
+        FaultReport {
+            kind,
+            stack,
+            sp,
+            // With the flip-link layout an overflowing stack points below RAM
+            frame: if stack == Stack::Main && sp < stack::limit() {
+                ExceptionFrame::default()
+            } else {
+                *frame
+            },
+            cfsr: scb.cfsr.read(),
+            hfsr: scb.hfsr.read(),
+            mmfar: scb.mmfar.read(),
+            bfar: scb.bfar.read(),
+        }
+    }
+
+    /// Whether the main stack overflowed into the guard region
+    pub fn is_stack_overflow(&self) -> bool {
+        let stacking = self.cfsr & (CFSR_MSTKERR | CFSR_BSTKERR) != 0;
+        let guard_access = self.cfsr & CFSR_MMARVALID != 0 && stack::in_guard(self.mmfar);
+        self.stack == Stack::Main
+            && (guard_access || (stacking && self.sp < stack::limit() + stack::GUARD_SIZE))
+    }
+
+    /// Decoded cause of the fault, HardFaults that were escalated report the cause of
+    /// the original fault
+    pub fn cause(&self) -> Option<&'static str> {
+        if self.is_stack_overflow() {
+            return Some("stack overflow");
+        }
+        let cfsr = self.cfsr & self.kind.cfsr_mask();
+        CFSR_CAUSES
+            .iter()
+            .find(|&&(bit, _)| cfsr & bit != 0)
+            .map(|&(_, cause)| cause)
+            .or_else(|| {
+                if self.kind == FaultKind::HardFault && self.hfsr & HFSR_VECTTBL != 0 {
+                    Some("vector table read fault")
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = &self.frame;
+        writeln!(
+            f,
+            "{:?}: {}",
+            self.kind,
+            self.cause().unwrap_or("unknown cause")
+        )?;
+        writeln!(f, "stack: {:?} sp: {:#010x}", self.stack, self.sp)?;
+        writeln!(
+            f,
+            "r0:  {:#010x} r1: {:#010x} r2: {:#010x} r3:   {:#010x}",
+            frame.r0, frame.r1, frame.r2, frame.r3
+        )?;
+        writeln!(
+            f,
+            "r12: {:#010x} lr: {:#010x} pc: {:#010x} xpsr: {:#010x}",
+            frame.r12, frame.lr, frame.pc, frame.xpsr
+        )?;
+        writeln!(f, "CFSR: {:#010x} HFSR: {:#010x}", self.cfsr, self.hfsr)?;
+
+        if self.hfsr & HFSR_VECTTBL != 0 {
+            writeln!(f, "  vector table read fault")?;
+        }
+        if self.hfsr & HFSR_FORCED != 0 {
+            writeln!(f, "  escalated from a configurable fault")?;
+        }
+        if self.hfsr & HFSR_DEBUGEVT != 0 {
+            writeln!(f, "  debug event")?;
+        }
+        for &(bit, cause) in CFSR_CAUSES.iter() {
+            if self.cfsr & bit != 0 {
+                writeln!(f, "  {}", cause)?;
+            }
+        }
+        if self.cfsr & CFSR_MMARVALID != 0 {
+            writeln!(f, "MMFAR: {:#010x}", self.mmfar)?;
+        }
+        if self.cfsr & CFSR_BFARVALID != 0 {
+            writeln!(f, "BFAR: {:#010x}", self.bfar)?;
+        }
+        Ok(())
+    }
+}
+
+/// Latest fault report, kept for debuggers
+#[no_mangle]
+pub static mut FAULT_REPORT: Option<FaultReport> = None;
+
+/// Enable the MemManage, BusFault and UsageFault handlers and the optional traps
+pub unsafe fn enable_configurable_faults() {
+    let scb = &*SCB::ptr();
+    scb.shcsr
+        .modify(|r| r | SHCSR_MEMFAULTENA | SHCSR_BUSFAULTENA | SHCSR_USGFAULTENA);
+
+    #[cfg(feature = "unaligned-trap")]
+    scb.ccr.modify(|r| r | CCR_UNALIGN_TRP);
+    #[cfg(feature = "div0-trap")]
+    scb.ccr.modify(|r| r | CCR_DIV_0_TRP);
+
+    ::cortex_m::asm::dsb();
+    ::cortex_m::asm::isb();
+}
+
+/// ARM Hard-Fault Handler
+#[naked]
+pub unsafe extern "C" fn hardfault_handler() {
+    asm!("
+        movs r2, #0
+        b fault_entry
+    " :::: "volatile");
+}
+
+/// ARM MemManage Handler
+#[naked]
+pub unsafe extern "C" fn memmanage_handler() {
+    asm!("
+        movs r2, #1
+        b fault_entry
+    " :::: "volatile");
+}
+
+/// ARM BusFault Handler
+#[naked]
+pub unsafe extern "C" fn busfault_handler() {
+    asm!("
+        movs r2, #2
+        b fault_entry
+    " :::: "volatile");
+}
+
+/// ARM UsageFault Handler
+#[naked]
+pub unsafe extern "C" fn usagefault_handler() {
+    asm!("
+        movs r2, #3
+        b fault_entry
+    " :::: "volatile");
+}
+
+/// Common fault entry, expects the `FaultKind` in r2
+///
+/// Passes the active stack pointer, selected by bit 2 of EXC_RETURN, to `handle_fault`.
+/// The MPU is disabled first and if the main stack has run into the stack guard the
+/// handler continues on the top of the main stack.
+#[naked]
+#[no_mangle]
+unsafe extern "C" fn fault_entry() {
+    asm!("
+        movw r0, #0xED94
+        movt r0, #0xE000
+        movs r1, #0
+        str r1, [r0]
+        dsb
+        isb
+
+        tst lr, #4
+        bne 1f
+        mrs r0, msp
+        movw r3, #:lower16:__StackLimit
+        movt r3, #:upper16:__StackLimit
+        adds r3, #32
+        cmp r0, r3
+        bhs 2f
+        movw r3, #:lower16:__StackTop
+        movt r3, #:upper16:__StackTop
+        msr msp, r3
+        b 2f
+    1:
+        mrs r0, psp
+    2:
+        mov r1, lr
+        b handle_fault
+    " :::: "volatile");
+}
+
+#[no_mangle]
+unsafe extern "C" fn handle_fault(frame: *const ExceptionFrame, exc_return: u32, kind: u32) -> ! {
+    let kind = match kind {
+        1 => FaultKind::MemManage,
+        2 => FaultKind::BusFault,
+        3 => FaultKind::UsageFault,
+        _ => FaultKind::HardFault,
+    };
+    let stack = if exc_return & (1 << 2) == 0 {
+        Stack::Main
+    } else {
+        Stack::Process
+    };
+    let report = FaultReport::capture(kind, stack, &*frame);
+    FAULT_REPORT = Some(report);
+    crash::record_fault(&report);
+
+    let mut console = Console::new();
+    if report.is_stack_overflow() {
+        let _ = write!(console, "\r\n*** stack overflow ***\r\n{}", report);
+    } else {
+        // The report starts with the kind of fault
+        let _ = write!(console, "\r\n*** fault ***\r\n{}", report);
+    }
+
+    if cfg!(feature = "fault-reset") {
+        ::peripherals::power::system_reset();
+    }
+    halt()
+}
+
+/// Stop in the debugger if one is attached, otherwise spin
+pub(crate) fn halt() -> ! {
+    let dcb = unsafe { &*DCB::ptr() };
+    if dcb.dhcsr.read() & DHCSR_C_DEBUGEN != 0 {
+        ::cortex_m::asm::bkpt();
+    }
+    loop {}
+}
@@ -0,0 +1,163 @@
+//! Floating point unit
+//!
+//! `reset_handler` grants full access to CP10 and CP11 and enables automatic and lazy
+//! stacking of the FPU context, so hardware float code built for `thumbv7em-none-eabihf`
+//! runs without faulting. The space for s0-s15 and FPSCR is reserved on exception entry
+//! but only written if the handler uses the FPU.
+//!
+//! The FPU exception flags are sticky in FPSCR and raise the `FPU` interrupt.
+//! `enable_exceptions` installs a handler that clears them in the interrupted context and
+//! records invalid operations, divisions by zero and overflows in `errors()`.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cortex_m::asm;
+use interrupt::{self, Interrupt};
+
+const CPACR: *mut u32 = 0xE000_ED88 as *mut u32;
+const FPCCR: *mut u32 = 0xE000_EF34 as *mut u32;
+const FPCAR: *const u32 = 0xE000_EF38 as *const u32;
+
+/// Full access to CP10 and CP11
+const CPACR_CP10_CP11_FULL: u32 = 0xF << 20;
+
+const FPCCR_LSPACT: u32 = 1 << 0;
+const FPCCR_LSPEN: u32 = 1 << 30;
+const FPCCR_ASPEN: u32 = 1 << 31;
+
+/// Offset of FPSCR in the FPU context that is reserved on exception entry
+const STACKED_FPSCR_OFFSET: u32 = 0x40;
+
+/// Cumulative exception flags in FPSCR
+const FPSCR_EXCEPTIONS: u32 = 0x0000_009F;
+
+/// Errors that have been raised since the last `take_errors`
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Called from the `FPU` interrupt handler
+static mut ERROR_HOOK: Option<fn(FpuErrors)> = None;
+
+/// FPU exception flags
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct FpuErrors(u32);
+
+impl FpuErrors {
+    const IOC: u32 = 1 << 0;
+    const DZC: u32 = 1 << 1;
+    const OFC: u32 = 1 << 2;
+
+    /// Flags that are reported as errors, underflow and inexact results are expected
+    const MASK: u32 = Self::IOC | Self::DZC | Self::OFC;
+
+    /// Invalid operation, e.g. `0.0 / 0.0` or the square root of a negative number
+    pub fn invalid_operation(&self) -> bool {
+        self.0 & Self::IOC != 0
+    }
+
+    /// Division by zero
+    pub fn divide_by_zero(&self) -> bool {
+        self.0 & Self::DZC != 0
+    }
+
+    /// Overflow
+    pub fn overflow(&self) -> bool {
+        self.0 & Self::OFC != 0
+    }
+
+    /// Whether no error has been raised
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Raw FPSCR flags
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Debug for FpuErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FpuErrors(")?;
+        let mut sep = "";
+        for &(set, name) in [
+            (self.invalid_operation(), "invalid operation"),
+            (self.divide_by_zero(), "divide by zero"),
+            (self.overflow(), "overflow"),
+        ]
+        .iter()
+        {
+            if set {
+                write!(f, "{}{}", sep, name)?;
+                sep = ", ";
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+/// Enable the FPU with automatic and lazy state preservation, called from
+/// `reset_handler`
+pub(crate) unsafe fn enable() {
+    CPACR.write_volatile(CPACR.read_volatile() | CPACR_CP10_CP11_FULL);
+    FPCCR.write_volatile(FPCCR.read_volatile() | FPCCR_ASPEN | FPCCR_LSPEN);
+    asm::dsb();
+    asm::isb();
+}
+
+/// Handle the `FPU` interrupt and call `hook` for every invalid operation, division by
+/// zero and overflow
+pub fn enable_exceptions(hook: Option<fn(FpuErrors)>) {
+    ::cortex_m::interrupt::free(|_| unsafe { ERROR_HOOK = hook });
+    interrupt::register_handler(Interrupt::FPU, fpu_interrupt);
+    interrupt::enable(Interrupt::FPU);
+}
+
+/// Errors that have been raised since the last call
+pub fn take_errors() -> FpuErrors {
+    FpuErrors(ERRORS.swap(0, Ordering::Relaxed) as u32)
+}
+
+/// Errors that have been raised since the last `take_errors`
+pub fn errors() -> FpuErrors {
+    FpuErrors(ERRORS.load(Ordering::Relaxed) as u32)
+}
+
+/// Clear the exception flags of the interrupted context
+///
+/// If the interrupted context has an FPU context, FPCCR.LSPACT is set on entry and space
+/// for it has been reserved at FPCAR. Reading FPSCR then triggers the lazy state
+/// preservation and the flags are restored from the stacked FPSCR on exception return, so
+/// they must be cleared there. Otherwise FPCAR may be stale and the flags are cleared in
+/// FPSCR itself.
+fn fpu_interrupt() {
+    unsafe {
+        let stacked_context = FPCCR.read_volatile() & FPCCR_LSPACT != 0;
+
+        let fpscr: u32;
+        asm!("
+            .fpu fpv4-sp-d16
+            vmrs $0, fpscr
+        " : "=r"(fpscr) ::: "volatile");
+
+        let flags = if stacked_context {
+            let stacked = (FPCAR.read_volatile() + STACKED_FPSCR_OFFSET) as *mut u32;
+            let stacked_fpscr = stacked.read_volatile();
+            stacked.write_volatile(stacked_fpscr & !FPSCR_EXCEPTIONS);
+            stacked_fpscr | fpscr
+        } else {
+            asm!("
+                .fpu fpv4-sp-d16
+                vmsr fpscr, $0
+            " :: "r"(fpscr & !FPSCR_EXCEPTIONS) :: "volatile");
+            fpscr
+        };
+
+        let errors = flags & FpuErrors::MASK;
+        if errors != 0 {
+            ERRORS.fetch_or(errors as usize, Ordering::Relaxed);
+            if let Some(hook) = ERROR_HOOK {
+                hook(FpuErrors(errors));
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Heap allocator
+//!
+//! First-fit allocator over the `.heap` region in `nrf52.ld`, which spans `__heap_size__`
+//! bytes from the end of `.bss`. Free memory is kept in a list of holes sorted by
+//! address, neighbouring holes are merged when a block is freed.
+//!
+//! The heap is 8K by default, which `build.rs` only reserves with the `alloc` feature. The
+//! size is configured with the linker argument `--defsym=__heap_size__=<bytes>`.
+//!
+//! Allocation failures end up in the panic handler.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::mem;
+use core::ptr;
+use cortex_m::interrupt;
+
+/// Smallest block and alignment that is handed out, a hole must fit in every block
+const BLOCK_ALIGN: usize = 8;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator {
+    heap: UnsafeCell::new(Heap::empty()),
+};
+
+/// Symbols that are exported from the linker script
+extern "C" {
+    /// Start of the heap
+    static mut __end__: u32;
+
+    /// End of the heap
+    static mut __HeapLimit: u32;
+}
+
+/// Heap usage statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Size of the heap in bytes
+    pub size: usize,
+    /// Bytes that currently are allocated, including padding
+    pub used: usize,
+    /// Highest value of `used` since reset
+    pub peak: usize,
+    /// Number of successful allocations
+    pub allocations: usize,
+    /// Number of failed allocations
+    pub failures: usize,
+}
+
+/// Free block, stored in the block itself
+struct Hole {
+    size: usize,
+    next: *mut Hole,
+}
+
+struct Heap {
+    /// First hole, sorted by address
+    holes: *mut Hole,
+    stats: HeapStats,
+}
+
+struct Allocator {
+    heap: UnsafeCell<Heap>,
+}
+
+unsafe impl Sync for Allocator {}
+
+/// Hand the `.heap` region to the allocator, called from `reset_handler`
+pub(crate) unsafe fn init() {
+    let start = align_up(&__end__ as *const u32 as usize, BLOCK_ALIGN);
+    let end = &__HeapLimit as *const u32 as usize & !(BLOCK_ALIGN - 1);
+    (*ALLOCATOR.heap.get()).init(start, end.saturating_sub(start));
+}
+
+/// Current heap usage
+pub fn stats() -> HeapStats {
+    interrupt::free(|_| unsafe { (*ALLOCATOR.heap.get()).stats })
+}
+
+impl Heap {
+    const fn empty() -> Self {
+        Heap {
+            holes: ptr::null_mut(),
+            stats: HeapStats {
+                size: 0,
+                used: 0,
+                peak: 0,
+                allocations: 0,
+                failures: 0,
+            },
+        }
+    }
+
+    unsafe fn init(&mut self, start: usize, size: usize) {
+        self.stats = HeapStats {
+            size,
+            ..HeapStats::default()
+        };
+        self.holes = ptr::null_mut();
+        if size >= mem::size_of::<Hole>() {
+            self.holes = start as *mut Hole;
+            ptr::write(
+                self.holes,
+                Hole {
+                    size,
+                    next: ptr::null_mut(),
+                },
+            );
+        }
+    }
+
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = block_size(&layout);
+        let align = layout.align().max(BLOCK_ALIGN);
+
+        let mut prev: *mut Hole = ptr::null_mut();
+        let mut hole = self.holes;
+        while !hole.is_null() {
+            let hole_start = hole as usize;
+            let hole_end = hole_start + (*hole).size;
+            let next = (*hole).next;
+            // Everything is a multiple of `BLOCK_ALIGN` so the padding in front of and
+            // behind the block is either empty or large enough for a new hole
+            let block_start = align_up(hole_start, align);
+
+            if block_start + size <= hole_end {
+                let mut link = next;
+                if block_start + size < hole_end {
+                    let rest = (block_start + size) as *mut Hole;
+                    ptr::write(
+                        rest,
+                        Hole {
+                            size: hole_end - block_start - size,
+                            next: link,
+                        },
+                    );
+                    link = rest;
+                }
+                if block_start > hole_start {
+                    (*hole).size = block_start - hole_start;
+                    (*hole).next = link;
+                } else if prev.is_null() {
+                    self.holes = link;
+                } else {
+                    (*prev).next = link;
+                }
+
+                self.stats.used += size;
+                self.stats.peak = self.stats.peak.max(self.stats.used);
+                self.stats.allocations += 1;
+                return block_start as *mut u8;
+            }
+
+            prev = hole;
+            hole = next;
+        }
+
+        self.stats.failures += 1;
+        ptr::null_mut()
+    }
+
+    unsafe fn deallocate(&mut self, block: *mut u8, layout: Layout) {
+        let start = block as usize;
+        let size = block_size(&layout);
+
+        // Find the holes before and after the block
+        let mut prev: *mut Hole = ptr::null_mut();
+        let mut next = self.holes;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let hole = start as *mut Hole;
+        ptr::write(hole, Hole { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*hole).size += (*next).size;
+            (*hole).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.holes = hole;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*hole).size;
+            (*prev).next = (*hole).next;
+        } else {
+            (*prev).next = hole;
+        }
+
+        self.stats.used -= size;
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupt::free(|_| (*self.heap.get()).allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupt::free(|_| (*self.heap.get()).deallocate(ptr, layout))
+    }
+}
+
+/// Size of the block that is used for `layout`
+fn block_size(layout: &Layout) -> usize {
+    align_up(layout.size().max(BLOCK_ALIGN), BLOCK_ALIGN)
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!(
+        "out of memory: {} bytes aligned to {} ({:?})",
+        layout.size(),
+        layout.align(),
+        stats()
+    );
+}
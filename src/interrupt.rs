@@ -0,0 +1,463 @@
+//! Interrupts and the chip specific vector table
+//!
+//! Every slot in `ISR` points to a weak symbol named after the interrupt. The symbols
+//! default to `default_irq_handler` (see `nrf52.ld`) and can be overridden by the
+//! application or a driver by defining a function with the same name:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn RTC1() {
+//!     // handle the interrupt
+//! }
+//! ```
+//!
+//! As an alternative to link-time overrides handlers can be attached at runtime with
+//! `register_handler`, `register_handler_with_context` or `register_closure`. They are
+//! dispatched from `default_irq_handler` so they are only called for interrupts without
+//! a link-time handler. `default_irq_handler` reports the number of the interrupt that
+//! has no handler at all.
+//!
+//! nRF52 implements 3 priority bits, i.e. 8 levels where 0 is the most urgent. All
+//! interrupts have priority 0 after reset.
+
+use core::mem;
+use cortex_m::interrupt::{self, Nr};
+use cortex_m::peripheral::{NVIC, SCB};
+use cortex_m::register::{basepri, basepri_max};
+
+/// Number of interrupt lines on nRF52832
+pub const NUM_IRQS: usize = 39;
+
+/// `nRF52` Interrupts
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    #[doc = "0 - POWER_CLOCK"]
+    POWER_CLOCK,
+    #[doc = "1 - RADIO"]
+    RADIO,
+    #[doc = "2 - UARTE0_UART0"]
+    UARTE0_UART0,
+    #[doc = "3 - SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0"]
+    SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0,
+    #[doc = "4 - SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1"]
+    SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1,
+    #[doc = "5 - NFCT"]
+    NFCT,
+    #[doc = "6 - GPIOTE"]
+    GPIOTE,
+    #[doc = "7 - SAADC"]
+    SAADC,
+    #[doc = "8 - TIMER0"]
+    TIMER0,
+    #[doc = "9 - TIMER1"]
+    TIMER1,
+    #[doc = "10 - TIMER2"]
+    TIMER2,
+    #[doc = "11 - RTC0"]
+    RTC0,
+    #[doc = "12 - TEMP"]
+    TEMP,
+    #[doc = "13 - RNG"]
+    RNG,
+    #[doc = "14 - ECB"]
+    ECB,
+    #[doc = "15 - CCM_AAR"]
+    CCM_AAR,
+    #[doc = "16 - WDT"]
+    WDT,
+    #[doc = "17 - RTC1"]
+    RTC1,
+    #[doc = "18 - QDEC"]
+    QDEC,
+    #[doc = "19 - COMP_LPCOMP"]
+    COMP_LPCOMP,
+    #[doc = "20 - SWI0_EGU0"]
+    SWI0_EGU0,
+    #[doc = "21 - SWI1_EGU1"]
+    SWI1_EGU1,
+    #[doc = "22 - SWI2_EGU2"]
+    SWI2_EGU2,
+    #[doc = "23 - SWI3_EGU3"]
+    SWI3_EGU3,
+    #[doc = "24 - SWI4_EGU4"]
+    SWI4_EGU4,
+    #[doc = "25 - SWI5_EGU5"]
+    SWI5_EGU5,
+    #[doc = "26 - TIMER3"]
+    TIMER3,
+    #[doc = "27 - TIMER4"]
+    TIMER4,
+    #[doc = "28 - PWM0"]
+    PWM0,
+    #[doc = "29 - PDM"]
+    PDM,
+    #[doc = "32 - MWU"]
+    MWU,
+    #[doc = "33 - PWM1"]
+    PWM1,
+    #[doc = "34 - PWM2"]
+    PWM2,
+    #[doc = "35 - SPIM2_SPIS2_SPI2"]
+    SPIM2_SPIS2_SPI2,
+    #[doc = "36 - RTC2"]
+    RTC2,
+    #[doc = "37 - I2S"]
+    I2S,
+    #[doc = "38 - FPU"]
+    FPU,
+}
+
+unsafe impl Nr for Interrupt {
+    #[inline]
+    fn nr(&self) -> u8 {
+        match *self {
+            Interrupt::POWER_CLOCK => 0,
+            Interrupt::RADIO => 1,
+            Interrupt::UARTE0_UART0 => 2,
+            Interrupt::SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0 => 3,
+            Interrupt::SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1 => 4,
+            Interrupt::NFCT => 5,
+            Interrupt::GPIOTE => 6,
+            Interrupt::SAADC => 7,
+            Interrupt::TIMER0 => 8,
+            Interrupt::TIMER1 => 9,
+            Interrupt::TIMER2 => 10,
+            Interrupt::RTC0 => 11,
+            Interrupt::TEMP => 12,
+            Interrupt::RNG => 13,
+            Interrupt::ECB => 14,
+            Interrupt::CCM_AAR => 15,
+            Interrupt::WDT => 16,
+            Interrupt::RTC1 => 17,
+            Interrupt::QDEC => 18,
+            Interrupt::COMP_LPCOMP => 19,
+            Interrupt::SWI0_EGU0 => 20,
+            Interrupt::SWI1_EGU1 => 21,
+            Interrupt::SWI2_EGU2 => 22,
+            Interrupt::SWI3_EGU3 => 23,
+            Interrupt::SWI4_EGU4 => 24,
+            Interrupt::SWI5_EGU5 => 25,
+            Interrupt::TIMER3 => 26,
+            Interrupt::TIMER4 => 27,
+            Interrupt::PWM0 => 28,
+            Interrupt::PDM => 29,
+            Interrupt::MWU => 32,
+            Interrupt::PWM1 => 33,
+            Interrupt::PWM2 => 34,
+            Interrupt::SPIM2_SPIS2_SPI2 => 35,
+            Interrupt::RTC2 => 36,
+            Interrupt::I2S => 37,
+            Interrupt::FPU => 38,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+extern "C" {
+    fn POWER_CLOCK();
+    fn RADIO();
+    fn UARTE0_UART0();
+    fn SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0();
+    fn SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1();
+    fn NFCT();
+    fn GPIOTE();
+    fn SAADC();
+    fn TIMER0();
+    fn TIMER1();
+    fn TIMER2();
+    fn RTC0();
+    fn TEMP();
+    fn RNG();
+    fn ECB();
+    fn CCM_AAR();
+    fn WDT();
+    fn RTC1();
+    fn QDEC();
+    fn COMP_LPCOMP();
+    fn SWI0_EGU0();
+    fn SWI1_EGU1();
+    fn SWI2_EGU2();
+    fn SWI3_EGU3();
+    fn SWI4_EGU4();
+    fn SWI5_EGU5();
+    fn TIMER3();
+    fn TIMER4();
+    fn PWM0();
+    fn PDM();
+    fn MWU();
+    fn PWM1();
+    fn PWM2();
+    fn SPIM2_SPIS2_SPI2();
+    fn RTC2();
+    fn I2S();
+    fn FPU();
+}
+
+/// Interrupt Vector that is chip specific
+#[link_section = ".irqs"]
+#[cfg_attr(rustfmt, rustfmt_skip)]
+#[no_mangle] // Ensures that the symbol is kept until the final binary
+pub static ISR: [unsafe extern "C" fn(); NUM_IRQS] = [
+    POWER_CLOCK,                       // 0
+    RADIO,                             // 1
+    UARTE0_UART0,                      // 2
+    SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0, // 3
+    SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1, // 4
+    NFCT,                              // 5
+    GPIOTE,                            // 6
+    SAADC,                             // 7
+    TIMER0,                            // 8
+    TIMER1,                            // 9
+    TIMER2,                            // 10
+    RTC0,                              // 11
+    TEMP,                              // 12
+    RNG,                               // 13
+    ECB,                               // 14
+    CCM_AAR,                           // 15
+    WDT,                               // 16
+    RTC1,                              // 17
+    QDEC,                              // 18
+    COMP_LPCOMP,                       // 19
+    SWI0_EGU0,                         // 20
+    SWI1_EGU1,                         // 21
+    SWI2_EGU2,                         // 22
+    SWI3_EGU3,                         // 23
+    SWI4_EGU4,                         // 24
+    SWI5_EGU5,                         // 25
+    TIMER3,                            // 26
+    TIMER4,                            // 27
+    PWM0,                              // 28
+    PDM,                               // 29
+    default_irq_handler,               // 30 - Reserved
+    default_irq_handler,               // 31 - Reserved
+    MWU,                               // 32
+    PWM1,                              // 33
+    PWM2,                              // 34
+    SPIM2_SPIS2_SPI2,                  // 35
+    RTC2,                              // 36
+    I2S,                               // 37
+    FPU,                               // 38
+];
+
+/// Number of priority bits implemented in the NVIC
+pub const PRIORITY_BITS: u8 = 3;
+
+/// AIRCR fields, shared with `power::system_reset`
+pub(crate) const AIRCR_VECTKEY: u32 = 0x05FA << 16;
+const AIRCR_PRIGROUP_OFFSET: u32 = 8;
+pub(crate) const AIRCR_PRIGROUP_MASK: u32 = 0x7 << AIRCR_PRIGROUP_OFFSET;
+pub(crate) const AIRCR_SYSRESETREQ: u32 = 1 << 2;
+
+/// Interrupt priority level, 0 is the most urgent and 7 the least urgent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(u8);
+
+impl Priority {
+    /// Most urgent priority
+    pub const HIGHEST: Priority = Priority(0);
+    /// Least urgent priority
+    pub const LOWEST: Priority = Priority((1 << PRIORITY_BITS) - 1);
+
+    /// Priority level `level` (0 - 7)
+    pub fn new(level: u8) -> Self {
+        assert!(level < 1 << PRIORITY_BITS, "priority level out of range");
+        Priority(level)
+    }
+
+    /// Priority level
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    /// Value of the priority as written to the NVIC, the implemented bits are the MSBs
+    pub fn to_hw(&self) -> u8 {
+        self.0 << (8 - PRIORITY_BITS)
+    }
+
+    /// Priority from a value as read from the NVIC
+    pub fn from_hw(hw: u8) -> Self {
+        Priority(hw >> (8 - PRIORITY_BITS))
+    }
+}
+
+/// Split of the priority bits into preemption priority and sub-priority
+///
+/// Interrupts with the same preemption priority don't preempt each other, the
+/// sub-priority only decides which pending interrupt is taken first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriorityGrouping {
+    /// 8 preemption levels, no sub-priority (reset default)
+    Preempt3Sub0 = 4,
+    /// 4 preemption levels, 2 sub-priorities
+    Preempt2Sub1 = 5,
+    /// 2 preemption levels, 4 sub-priorities
+    Preempt1Sub2 = 6,
+    /// No preemption, 8 sub-priorities
+    Preempt0Sub3 = 7,
+}
+
+/// Handler that is registered at runtime
+#[derive(Clone, Copy)]
+struct Handler {
+    function: unsafe fn(*mut ()),
+    context: *mut (),
+}
+
+/// RAM-backed dispatch table used by `default_irq_handler`
+static mut HANDLERS: [Option<Handler>; NUM_IRQS] = [None; NUM_IRQS];
+
+/// Interrupt number of the latest unhandled interrupt, kept for debuggers
+#[no_mangle]
+pub static mut UNHANDLED_IRQ: i16 = -1;
+
+/// Default handler for interrupts without a link-time handler
+#[no_mangle]
+pub unsafe extern "C" fn default_irq_handler() {
+    let irqn = active_irq();
+    if irqn >= 0 {
+        if let Some(handler) = HANDLERS[irqn as usize] {
+            (handler.function)(handler.context);
+            return;
+        }
+    }
+    UNHANDLED_IRQ = irqn;
+    panic!("unhandled interrupt: IRQ {}", irqn);
+}
+
+/// Number of the interrupt that is being serviced, negative for core exceptions
+pub fn active_irq() -> i16 {
+    let ipsr: u32;
+    unsafe {
+        asm!("mrs $0, IPSR" : "=r"(ipsr) ::: "volatile");
+    }
+    (ipsr & 0x1FF) as i16 - 16
+}
+
+/// Attach `handler` to `irq`, replacing any previously registered handler
+pub fn register_handler(irq: Interrupt, handler: fn()) {
+    unsafe fn call(context: *mut ()) {
+        let handler: fn() = mem::transmute(context);
+        handler();
+    }
+
+    unsafe { register_handler_with_context(irq, call, handler as *mut ()) }
+}
+
+/// Attach `handler` to `irq`, `context` is passed to the handler on every interrupt
+///
+/// The caller must ensure that `context` is valid as long as the handler is registered
+pub unsafe fn register_handler_with_context(
+    irq: Interrupt,
+    handler: unsafe fn(*mut ()),
+    context: *mut (),
+) {
+    interrupt::free(|_| {
+        HANDLERS[irq.nr() as usize] = Some(Handler {
+            function: handler,
+            context,
+        });
+    });
+}
+
+/// Attach a closure to `irq`
+pub fn register_closure<F>(irq: Interrupt, closure: &'static mut F)
+where
+    F: FnMut() + Send,
+{
+    unsafe fn call<F: FnMut()>(context: *mut ()) {
+        (*(context as *mut F))();
+    }
+
+    unsafe { register_handler_with_context(irq, call::<F>, closure as *mut F as *mut ()) }
+}
+
+/// Detach the runtime handler of `irq`
+pub fn unregister_handler(irq: Interrupt) {
+    interrupt::free(|_| unsafe {
+        HANDLERS[irq.nr() as usize] = None;
+    });
+}
+
+/// Enable `irq` in the NVIC
+pub fn enable(irq: Interrupt) {
+    let nr = irq.nr() as usize;
+    unsafe { (*NVIC::ptr()).iser[nr / 32].write(1 << (nr % 32)) }
+}
+
+/// Disable `irq` in the NVIC
+pub fn disable(irq: Interrupt) {
+    let nr = irq.nr() as usize;
+    unsafe { (*NVIC::ptr()).icer[nr / 32].write(1 << (nr % 32)) }
+}
+
+/// Check whether `irq` is enabled in the NVIC
+pub fn is_enabled(irq: Interrupt) -> bool {
+    let nr = irq.nr() as usize;
+    unsafe { (*NVIC::ptr()).iser[nr / 32].read() & (1 << (nr % 32)) != 0 }
+}
+
+/// Set `irq` pending
+pub fn pend(irq: Interrupt) {
+    let nr = irq.nr() as usize;
+    unsafe { (*NVIC::ptr()).ispr[nr / 32].write(1 << (nr % 32)) }
+}
+
+/// Clear the pending state of `irq`
+pub fn unpend(irq: Interrupt) {
+    let nr = irq.nr() as usize;
+    unsafe { (*NVIC::ptr()).icpr[nr / 32].write(1 << (nr % 32)) }
+}
+
+/// Set the priority of `irq`
+///
+/// Unsafe because changing priorities can break priority based critical sections
+pub unsafe fn set_priority(irq: Interrupt, priority: Priority) {
+    (*NVIC::ptr()).ipr[irq.nr() as usize].write(priority.to_hw());
+}
+
+/// Priority of `irq`
+pub fn priority(irq: Interrupt) -> Priority {
+    Priority::from_hw(unsafe { (*NVIC::ptr()).ipr[irq.nr() as usize].read() })
+}
+
+/// Set how the priority bits are split into preemption priority and sub-priority
+pub unsafe fn set_priority_grouping(grouping: PriorityGrouping) {
+    (*SCB::ptr()).aircr.modify(|r| {
+        AIRCR_VECTKEY
+            | (r & !(0xFFFF_0000 | AIRCR_PRIGROUP_MASK))
+            | ((grouping as u32) << AIRCR_PRIGROUP_OFFSET)
+    });
+}
+
+/// Current priority grouping
+pub fn priority_grouping() -> PriorityGrouping {
+    let aircr = unsafe { (*SCB::ptr()).aircr.read() };
+    match (aircr & AIRCR_PRIGROUP_MASK) >> AIRCR_PRIGROUP_OFFSET {
+        5 => PriorityGrouping::Preempt2Sub1,
+        6 => PriorityGrouping::Preempt1Sub2,
+        7 => PriorityGrouping::Preempt0Sub3,
+        // PRIGROUP 0 - 4 all give 3 preemption bits with 3 implemented bits
+        _ => PriorityGrouping::Preempt3Sub0,
+    }
+}
+
+/// Run `f` with all interrupts of priority `priority` or less urgent masked by BASEPRI
+///
+/// More urgent interrupts can still preempt `f`. Priority 0 can't be masked by BASEPRI,
+/// use `cortex_m::interrupt::free` for that.
+pub fn mask<F, R>(priority: Priority, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    assert!(
+        priority > Priority::HIGHEST,
+        "priority 0 can't be masked by BASEPRI"
+    );
+    let old = basepri::read();
+    // `basepri_max` only raises the masking level, nested calls can't lower it
+    unsafe { basepri_max::write(priority.to_hw()) };
+    let r = f();
+    unsafe { basepri::write(old) };
+    r
+}
@@ -0,0 +1,364 @@
+//! Preemptive priority based kernel
+//!
+//! Tasks run in thread mode on their own PSP stack, exceptions and interrupts keep using
+//! the MSP. The context switch is done in PendSV at the lowest exception priority:
+//!     * r4-r11 and EXC_RETURN are pushed to the task stack
+//!     * s16-s31 are pushed when the task has an active FPU context, s0-s15 and FPSCR are
+//!       stacked (lazily) by the hardware
+//!
+//! The most urgent ready task runs, 0 is the most urgent priority. Tasks with equal
+//! priority are time sliced on every SysTick. An idle task that sleeps with `wfi` runs
+//! when no other task is ready.
+//!
+//! ```ignore
+//! static mut STACK: [u32; 256] = [0; 256];
+//!
+//! fn blink(led: usize) -> ! {
+//!     loop {
+//!         unsafe { Pin::steal(led as u32) }.toggle();
+//!         kernel::sleep_ms(500);
+//!     }
+//! }
+//!
+//! kernel::spawn(blink, 17, 1, unsafe { &mut STACK });
+//! kernel::start(1000);
+//! ```
+
+use core::mem;
+#[cfg(feature = "stack-paint")]
+use core::slice;
+use cortex_m::interrupt;
+use cortex_m::peripheral::SCB;
+use peripherals::systick::SYSTICK;
+#[cfg(feature = "stack-paint")]
+use stack::{self, StackUsage};
+
+/// Blocking synchronization primitives
+pub mod sync;
+
+/// Maximum number of tasks including the idle task
+pub const MAX_TASKS: usize = 8;
+
+/// Priority of the idle task, less urgent than any other task
+pub const IDLE_PRIORITY: u8 = 0xFF;
+
+/// Minimum stack size in words
+pub const MIN_STACK_SIZE: usize = 64;
+
+const IDLE_TASK: usize = 0;
+const IDLE_STACK_SIZE: usize = MIN_STACK_SIZE;
+
+/// Return to thread mode using PSP without FPU context
+const EXC_RETURN_THREAD_PSP: u32 = 0xFFFF_FFFD;
+const XPSR_THUMB: u32 = 1 << 24;
+const ICSR_PENDSVSET: u32 = 1 << 28;
+
+/// r4-r11 and EXC_RETURN pushed by `pendsv_handler` plus the hardware frame
+const INITIAL_FRAME_WORDS: usize = 17;
+
+/// SHPR index of PendSV and SysTick
+const SHPR_PENDSV: usize = 10;
+const SHPR_SYSTICK: usize = 11;
+
+/// Task identifier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaskId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Free,
+    Ready,
+    Sleeping,
+    Blocked,
+}
+
+/// Task control block
+#[derive(Clone, Copy)]
+struct Tcb {
+    /// Saved PSP while the task isn't running
+    sp: u32,
+    priority: u8,
+    state: State,
+    /// Tick to wake up at while `Sleeping`
+    wake_tick: u32,
+    /// Object the task waits for while `Blocked`
+    wait_object: usize,
+    /// Lowest address of the task stack
+    stack_base: usize,
+    /// Size of the task stack in words
+    stack_len: usize,
+}
+
+const FREE_TCB: Tcb = Tcb {
+    sp: 0,
+    priority: IDLE_PRIORITY,
+    state: State::Free,
+    wake_tick: 0,
+    wait_object: 0,
+    stack_base: 0,
+    stack_len: 0,
+};
+
+static mut TASKS: [Tcb; MAX_TASKS] = [FREE_TCB; MAX_TASKS];
+
+/// Running task, `None` until the first context switch
+static mut CURRENT: Option<usize> = None;
+
+static mut IDLE_STACK: [u32; IDLE_STACK_SIZE] = [0; IDLE_STACK_SIZE];
+
+/// PSP for the context of `main` which is saved by the first context switch, it is never
+/// restored
+static mut BOOT_STACK: [u32; 32] = [0; 32];
+
+/// Create a task that runs `entry(arg)` on `stack`
+///
+/// The task can be created before or after `start`
+pub fn spawn(entry: fn(usize) -> !, arg: usize, priority: u8, stack: &'static mut [u32]) -> TaskId {
+    assert!(
+        priority < IDLE_PRIORITY,
+        "priority is reserved for the idle task"
+    );
+    interrupt::free(|_| unsafe {
+        let slot = (IDLE_TASK + 1..MAX_TASKS)
+            .find(|&i| TASKS[i].state == State::Free)
+            .expect("no free task slot");
+
+        TASKS[slot] = Tcb {
+            stack_base: stack.as_ptr() as usize,
+            stack_len: stack.len(),
+            sp: init_stack(stack, entry as usize, arg),
+            priority,
+            state: State::Ready,
+            wake_tick: 0,
+            wait_object: 0,
+        };
+
+        if let Some(current) = CURRENT {
+            if priority < TASKS[current].priority {
+                pend_switch();
+            }
+        }
+        TaskId(slot)
+    })
+}
+
+/// Start the scheduler with a time slice of one tick at `tick_hz`, never returns
+///
+/// A tick that already runs, e.g. for an executor `Timer`, is shared and keeps its tick rate
+pub fn start(tick_hz: u32) -> ! {
+    unsafe {
+        interrupt::disable();
+
+        TASKS[IDLE_TASK] = Tcb {
+            stack_base: IDLE_STACK.as_ptr() as usize,
+            stack_len: IDLE_STACK_SIZE,
+            sp: init_stack(&mut IDLE_STACK, idle as usize, 0),
+            priority: IDLE_PRIORITY,
+            state: State::Ready,
+            wake_tick: 0,
+            wait_object: 0,
+        };
+
+        // Context switches must not preempt other exceptions
+        let scb = &*SCB::ptr();
+        scb.shpr[SHPR_PENDSV].write(0xFF);
+        scb.shpr[SHPR_SYSTICK].write(0xFF);
+
+        let boot_sp = BOOT_STACK.as_ptr().add(BOOT_STACK.len()) as u32;
+        asm!("msr PSP, $0" :: "r"(boot_sp) :: "volatile");
+
+        SYSTICK.add_tick_hook(on_tick);
+        if SYSTICK.tick_hz() == 0 {
+            SYSTICK.start(tick_hz);
+        }
+
+        pend_switch();
+        interrupt::enable();
+    }
+
+    // PendSV is taken as soon as interrupts are enabled
+    loop {}
+}
+
+/// Peak stack usage of `task`, `None` if the task doesn't exist
+///
+/// The idle task is `TaskId(0)`. Exceptions run on the main stack, see
+/// `stack::main_usage`
+#[cfg(feature = "stack-paint")]
+pub fn stack_usage(task: TaskId) -> Option<StackUsage> {
+    interrupt::free(|_| unsafe {
+        let tcb = TASKS.get(task.0)?;
+        if tcb.state == State::Free {
+            return None;
+        }
+        let stack = slice::from_raw_parts(tcb.stack_base as *const u32, tcb.stack_len);
+        Some(stack::usage(stack))
+    })
+}
+
+/// Task that is running, `None` before `start`
+pub fn current() -> Option<TaskId> {
+    unsafe { CURRENT.map(TaskId) }
+}
+
+/// Give up the rest of the time slice
+pub fn yield_now() {
+    pend_switch();
+}
+
+/// Sleep for at least `ticks` ticks
+pub fn sleep(ticks: u32) {
+    interrupt::free(|_| unsafe {
+        let current = CURRENT.expect("kernel not started");
+        TASKS[current].state = State::Sleeping;
+        TASKS[current].wake_tick = SYSTICK.ticks().wrapping_add(ticks.max(1));
+        pend_switch();
+    });
+}
+
+/// Sleep for at least `ms` milliseconds
+pub fn sleep_ms(ms: u32) {
+    let hz = u64::from(SYSTICK.tick_hz());
+    sleep(((u64::from(ms) * hz + 999) / 1000) as u32);
+}
+
+/// Block the running task until `wake_one(object)`, the switch happens when the
+/// surrounding critical section is left
+pub(crate) unsafe fn block_current(object: usize) {
+    let current = CURRENT.expect("kernel not started");
+    TASKS[current].state = State::Blocked;
+    TASKS[current].wait_object = object;
+    pend_switch();
+}
+
+/// Wake the most urgent task waiting for `object`, must be called in a critical section
+pub(crate) unsafe fn wake_one(object: usize) -> bool {
+    let mut best: Option<usize> = None;
+    for (i, task) in TASKS.iter().enumerate() {
+        if task.state == State::Blocked
+            && task.wait_object == object
+            && best.map_or(true, |b| task.priority < TASKS[b].priority)
+        {
+            best = Some(i);
+        }
+    }
+
+    match best {
+        Some(woken) => {
+            TASKS[woken].state = State::Ready;
+            if CURRENT.map_or(true, |c| TASKS[woken].priority < TASKS[c].priority) {
+                pend_switch();
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+fn pend_switch() {
+    unsafe { (*SCB::ptr()).icsr.write(ICSR_PENDSVSET) };
+}
+
+/// Build the frame that `pendsv_handler` restores on the first switch to a task
+unsafe fn init_stack(stack: &'static mut [u32], entry: usize, arg: usize) -> u32 {
+    assert!(stack.len() >= MIN_STACK_SIZE, "task stack too small");
+
+    // The hardware frame must be 8 byte aligned
+    let top = stack.as_mut_ptr().add(stack.len()) as usize & !7;
+    let frame = (top as *mut u32).sub(INITIAL_FRAME_WORDS);
+
+    #[cfg(feature = "stack-paint")]
+    for word in stack.iter_mut() {
+        if (word as *mut u32) < frame {
+            *word = stack::PAINT_PATTERN;
+        }
+    }
+
+    // r4 - r11
+    for i in 0..8 {
+        *frame.add(i) = 0;
+    }
+    *frame.add(8) = EXC_RETURN_THREAD_PSP;
+    *frame.add(9) = entry as u32; // r0
+    *frame.add(10) = arg as u32; // r1
+    *frame.add(11) = 0; // r2
+    *frame.add(12) = 0; // r3
+    *frame.add(13) = 0; // r12
+    *frame.add(14) = 0; // lr
+    *frame.add(15) = task_trampoline as usize as u32 & !1; // pc
+    *frame.add(16) = XPSR_THUMB; // xpsr
+
+    frame as u32
+}
+
+extern "C" fn task_trampoline(entry: usize, arg: usize) -> ! {
+    let entry: fn(usize) -> ! = unsafe { mem::transmute(entry) };
+    entry(arg)
+}
+
+fn idle(_: usize) -> ! {
+    loop {
+        ::cortex_m::asm::wfi();
+    }
+}
+
+/// Wake sleeping tasks and time slice, called from the SysTick handler
+fn on_tick() {
+    interrupt::free(|_| unsafe {
+        let now = SYSTICK.ticks();
+        for task in TASKS.iter_mut() {
+            if task.state == State::Sleeping && now.wrapping_sub(task.wake_tick) as i32 >= 0 {
+                task.state = State::Ready;
+            }
+        }
+    });
+    pend_switch();
+}
+
+/// Select the most urgent ready task, round-robin between tasks of equal priority
+unsafe fn next_task() -> usize {
+    let start = CURRENT.map_or(0, |c| c + 1);
+    let mut best = IDLE_TASK;
+    for i in 0..MAX_TASKS {
+        let candidate = (start + i) % MAX_TASKS;
+        if TASKS[candidate].state == State::Ready
+            && TASKS[candidate].priority < TASKS[best].priority
+        {
+            best = candidate;
+        }
+    }
+    best
+}
+
+/// Save the stack pointer of the running task and return the one of the next task
+#[no_mangle]
+unsafe extern "C" fn kernel_switch(sp: u32) -> u32 {
+    interrupt::free(|_| {
+        if let Some(current) = CURRENT {
+            TASKS[current].sp = sp;
+        }
+        let next = next_task();
+        CURRENT = Some(next);
+        TASKS[next].sp
+    })
+}
+
+/// ARM PendSV Handler, performs the context switch
+#[naked]
+pub unsafe extern "C" fn pendsv_handler() {
+    asm!("
+        .fpu fpv4-sp-d16
+        mrs r0, psp
+        tst lr, #0x10
+        it eq
+        vstmdbeq r0!, {s16-s31}
+        stmdb r0!, {r4-r11, lr}
+        bl kernel_switch
+        ldmia r0!, {r4-r11, lr}
+        tst lr, #0x10
+        it eq
+        vldmiaeq r0!, {s16-s31}
+        msr psp, r0
+        bx lr
+    " :::: "volatile");
+}
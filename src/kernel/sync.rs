@@ -0,0 +1,266 @@
+//! Blocking synchronization primitives for kernel tasks
+//!
+//! Waiting blocks the calling task until the object is signalled, the most urgent waiting
+//! task is woken first. `Semaphore::signal`, `Queue::try_send` and `Queue::try_receive`
+//! don't block and can be used from interrupt handlers.
+
+use super::{block_current, wake_one};
+use core::cell::{Cell, UnsafeCell};
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use cortex_m::interrupt;
+
+/// Counting semaphore
+pub struct Semaphore {
+    count: Cell<u32>,
+}
+
+impl Semaphore {
+    /// Semaphore with `count` initial permits
+    pub const fn new(count: u32) -> Self {
+        Semaphore {
+            count: Cell::new(count),
+        }
+    }
+
+    /// Take a permit, blocks until one is available
+    pub fn wait(&self) {
+        while !interrupt::free(|_| {
+            let acquired = self.take();
+            if !acquired {
+                unsafe { block_current(self.id()) };
+            }
+            acquired
+        }) {}
+    }
+
+    /// Take a permit if one is available
+    pub fn try_wait(&self) -> bool {
+        interrupt::free(|_| self.take())
+    }
+
+    /// Release a permit and wake a waiting task
+    ///
+    /// Panics if the number of permits overflows
+    pub fn signal(&self) {
+        interrupt::free(|_| {
+            let count = self.count.get().checked_add(1);
+            self.count.set(count.expect("semaphore count overflow"));
+            unsafe { wake_one(self.id()) };
+        });
+    }
+
+    fn take(&self) -> bool {
+        let count = self.count.get();
+        if count > 0 {
+            self.count.set(count - 1);
+        }
+        count > 0
+    }
+
+    fn id(&self) -> usize {
+        self as *const _ as usize
+    }
+}
+
+unsafe impl Sync for Semaphore {}
+
+/// Mutual exclusion between tasks, must not be used from interrupt handlers
+pub struct Mutex<T> {
+    locked: Cell<bool>,
+    data: UnsafeCell<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Constructor
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            locked: Cell::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Lock the mutex, blocks until it is available
+    pub fn lock(&self) -> MutexGuard<T> {
+        while !interrupt::free(|_| {
+            let acquired = !self.locked.replace(true);
+            if !acquired {
+                unsafe { block_current(self.id()) };
+            }
+            acquired
+        }) {}
+        MutexGuard { mutex: self }
+    }
+
+    /// Lock the mutex if it is available
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if interrupt::free(|_| !self.locked.replace(true)) {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const _ as usize
+    }
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+/// Locked mutex, unlocked when dropped
+pub struct MutexGuard<'a, T: 'a> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        interrupt::free(|_| {
+            self.mutex.locked.set(false);
+            unsafe { wake_one(self.mutex.id()) };
+        });
+    }
+}
+
+/// Fixed capacity message queue
+///
+/// The storage is provided with `init` so the queue can be a `static`:
+///
+/// ```ignore
+/// static QUEUE: Queue<u32> = Queue::new();
+/// static mut STORAGE: [u32; 8] = [0; 8];
+///
+/// QUEUE.init(unsafe { &mut STORAGE });
+/// ```
+pub struct Queue<T> {
+    buffer: Cell<*mut T>,
+    capacity: Cell<usize>,
+    head: Cell<usize>,
+    len: Cell<usize>,
+}
+
+impl<T: Copy> Queue<T> {
+    /// Queue without storage, `init` must be called before use
+    pub const fn new() -> Self {
+        Queue {
+            buffer: Cell::new(ptr::null_mut()),
+            capacity: Cell::new(0),
+            head: Cell::new(0),
+            len: Cell::new(0),
+        }
+    }
+
+    /// Provide the storage of the queue, the capacity is the length of `storage`
+    pub fn init(&self, storage: &'static mut [T]) {
+        assert!(!storage.is_empty());
+        interrupt::free(|_| {
+            self.buffer.set(storage.as_mut_ptr());
+            self.capacity.set(storage.len());
+            self.head.set(0);
+            self.len.set(0);
+        });
+    }
+
+    /// Number of queued messages
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Send `item`, blocks while the queue is full
+    pub fn send(&self, item: T) {
+        while !interrupt::free(|_| {
+            let sent = self.push(item).is_ok();
+            if !sent {
+                unsafe { block_current(self.senders()) };
+            }
+            sent
+        }) {}
+    }
+
+    /// Send `item` if there is room for it, otherwise it is given back
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        interrupt::free(|_| self.push(item))
+    }
+
+    /// Receive a message, blocks while the queue is empty
+    pub fn receive(&self) -> T {
+        loop {
+            let item = interrupt::free(|_| {
+                let item = self.pop();
+                if item.is_none() {
+                    unsafe { block_current(self.receivers()) };
+                }
+                item
+            });
+            if let Some(item) = item {
+                return item;
+            }
+        }
+    }
+
+    /// Receive a message if one is available
+    pub fn try_receive(&self) -> Option<T> {
+        interrupt::free(|_| self.pop())
+    }
+
+    // Must be called in a critical section
+    fn push(&self, item: T) -> Result<(), T> {
+        let capacity = self.capacity.get();
+        assert!(capacity > 0, "queue is not initialized");
+        let len = self.len.get();
+        if len == capacity {
+            return Err(item);
+        }
+        unsafe {
+            *self.buffer.get().add((self.head.get() + len) % capacity) = item;
+            self.len.set(len + 1);
+            wake_one(self.receivers());
+        }
+        Ok(())
+    }
+
+    // Must be called in a critical section
+    fn pop(&self) -> Option<T> {
+        let len = self.len.get();
+        if len == 0 {
+            return None;
+        }
+        let head = self.head.get();
+        unsafe {
+            let item = *self.buffer.get().add(head);
+            self.head.set((head + 1) % self.capacity.get());
+            self.len.set(len - 1);
+            wake_one(self.senders());
+            Some(item)
+        }
+    }
+
+    fn receivers(&self) -> usize {
+        self as *const _ as usize
+    }
+
+    fn senders(&self) -> usize {
+        self.receivers() + 1
+    }
+}
+
+unsafe impl<T: Send> Sync for Queue<T> {}
@@ -25,26 +25,60 @@ impl Termination for () {
 extern "C" fn eh_personality() {}
 
 /// Panic Handler
+///
+/// Stores a crash record, prints the panic message and location on the console UART and
+/// then applies the policy that is selected by the cargo features:
+///     * `panic-halt`: stop in the debugger if one is attached, otherwise spin
+///     * `panic-reset`: reset the chip
+///     * default: blink the LEDs
 #[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    use board::LEDS;
-    use peripherals::gpio::GPIO;
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    use console::Console;
+    use core::fmt::Write;
+    use core::sync::atomic::{AtomicBool, Ordering};
 
-    unsafe {
-        let gpio = &GPIO;
-        for led in LEDS.iter() {
-            gpio.make_output(*led);
-        }
+    static PANICKING: AtomicBool = AtomicBool::new(false);
 
-        loop {
-            for _ in 0..100000 {}
+    ::cortex_m::interrupt::disable();
 
-            for led in LEDS.iter() {
-                gpio.toggle(*led);
-            }
+    // Don't record and print again if formatting the message panics
+    if !PANICKING.swap(true, Ordering::Relaxed) {
+        unsafe { ::crash::record_panic(info) };
+        let mut console = unsafe { Console::new() };
+        let _ = write!(console, "\r\n*** {} ***\r\n", info);
+    }
+
+    panic_policy()
+}
+
+#[cfg(all(not(test), feature = "panic-halt"))]
+fn panic_policy() -> ! {
+    ::fault::halt()
+}
 
-            for _ in 0..10000 {}
+#[cfg(all(not(test), feature = "panic-reset", not(feature = "panic-halt")))]
+fn panic_policy() -> ! {
+    ::peripherals::power::system_reset()
+}
+
+#[cfg(all(not(test), not(feature = "panic-halt"), not(feature = "panic-reset")))]
+fn panic_policy() -> ! {
+    use board::LEDS;
+    use peripherals::gpio::GPIO;
+    use peripherals::systick::SYSTICK;
+
+    // The LEDs are taken over without owning their pins, the panicking code might hold the
+    // lock and never returns to release it
+    let gpio = unsafe { GPIO.steal() };
+    for led in LEDS.iter() {
+        gpio.make_output(*led);
+    }
+
+    loop {
+        for led in LEDS.iter() {
+            gpio.toggle(*led);
         }
+        SYSTICK.delay_ms(100);
     }
 }
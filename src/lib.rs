@@ -12,9 +12,13 @@
 // #![warn(missing_docs)]
 #![crate_type = "staticlib"]
 #![feature(asm, lang_items, start, naked_functions, const_fn)]
+#![cfg_attr(feature = "alloc", feature(alloc, alloc_error_handler))]
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
 extern crate cortex_m;
+extern crate embedded_hal;
 #[macro_use]
 extern crate tock_registers;
 
@@ -23,134 +27,40 @@ mod lang_items;
 
 /// Board specific definitions
 pub mod board;
+/// Reset reason, retained boot mode and jumping to an application
+pub mod boot;
+/// Console on the UART
+pub mod console;
+/// Critical sections and a `Mutex` for the peripheral singletons
+pub mod critical_section;
+/// Crash records that survive a reset
+pub mod crash;
+/// Async executor driven by interrupts
+pub mod executor;
+/// Fault handlers
+pub mod fault;
+/// Floating point unit
+pub mod fpu;
+/// Heap allocator
+#[cfg(feature = "alloc")]
+pub mod heap;
+/// Interrupts and the chip specific vector table
+pub mod interrupt;
+/// Preemptive multitasking kernel
+pub mod kernel;
 /// Drivers for peripherals
 pub mod peripherals;
+/// Main stack guard
+pub mod stack;
+/// Supervisor calls
+pub mod syscall;
+/// ITM trace over SWO or the parallel trace port
+pub mod trace;
 
-use cortex_m::interrupt::Nr;
+pub use interrupt::Interrupt;
+pub use peripherals::Peripherals;
 
-/// `nRF52` Interrupts
-#[allow(non_camel_case_types)]
-pub enum Interrupt {
-    #[doc = "0 - POWER_CLOCK"]
-    POWER_CLOCK,
-    #[doc = "1 - RADIO"]
-    RADIO,
-    #[doc = "2 - UARTE0_UART0"]
-    UARTE0_UART0,
-    #[doc = "3 - SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0"]
-    SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0,
-    #[doc = "4 - SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1"]
-    SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1,
-    #[doc = "5 - NFCT"]
-    NFCT,
-    #[doc = "6 - GPIOTE"]
-    GPIOTE,
-    #[doc = "7 - SAADC"]
-    SAADC,
-    #[doc = "8 - TIMER0"]
-    TIMER0,
-    #[doc = "9 - TIMER1"]
-    TIMER1,
-    #[doc = "10 - TIMER2"]
-    TIMER2,
-    #[doc = "11 - RTC0"]
-    RTC0,
-    #[doc = "12 - TEMP"]
-    TEMP,
-    #[doc = "13 - RNG"]
-    RNG,
-    #[doc = "14 - ECB"]
-    ECB,
-    #[doc = "15 - CCM_AAR"]
-    CCM_AAR,
-    #[doc = "16 - WDT"]
-    WDT,
-    #[doc = "17 - RTC1"]
-    RTC1,
-    #[doc = "18 - QDEC"]
-    QDEC,
-    #[doc = "19 - COMP_LPCOMP"]
-    COMP_LPCOMP,
-    #[doc = "20 - SWI0_EGU0"]
-    SWI0_EGU0,
-    #[doc = "21 - SWI1_EGU1"]
-    SWI1_EGU1,
-    #[doc = "22 - SWI2_EGU2"]
-    SWI2_EGU2,
-    #[doc = "23 - SWI3_EGU3"]
-    SWI3_EGU3,
-    #[doc = "24 - SWI4_EGU4"]
-    SWI4_EGU4,
-    #[doc = "25 - SWI5_EGU5"]
-    SWI5_EGU5,
-    #[doc = "26 - TIMER3"]
-    TIMER3,
-    #[doc = "27 - TIMER4"]
-    TIMER4,
-    #[doc = "28 - PWM0"]
-    PWM0,
-    #[doc = "29 - PDM"]
-    PDM,
-    #[doc = "32 - MWU"]
-    MWU,
-    #[doc = "33 - PWM1"]
-    PWM1,
-    #[doc = "34 - PWM2"]
-    PWM2,
-    #[doc = "35 - SPIM2_SPIS2_SPI2"]
-    SPIM2_SPIS2_SPI2,
-    #[doc = "36 - RTC2"]
-    RTC2,
-    #[doc = "37 - I2S"]
-    I2S,
-    #[doc = "38 - FPU"]
-    FPU,
-}
-
-unsafe impl Nr for Interrupt {
-    #[inline]
-    fn nr(&self) -> u8 {
-        match *self {
-            Interrupt::POWER_CLOCK => 0,
-            Interrupt::RADIO => 1,
-            Interrupt::UARTE0_UART0 => 2,
-            Interrupt::SPIM0_SPIS0_TWIM0_TWIS0_SPI0_TWI0 => 3,
-            Interrupt::SPIM1_SPIS1_TWIM1_TWIS1_SPI1_TWI1 => 4,
-            Interrupt::NFCT => 5,
-            Interrupt::GPIOTE => 6,
-            Interrupt::SAADC => 7,
-            Interrupt::TIMER0 => 8,
-            Interrupt::TIMER1 => 9,
-            Interrupt::TIMER2 => 10,
-            Interrupt::RTC0 => 11,
-            Interrupt::TEMP => 12,
-            Interrupt::RNG => 13,
-            Interrupt::ECB => 14,
-            Interrupt::CCM_AAR => 15,
-            Interrupt::WDT => 16,
-            Interrupt::RTC1 => 17,
-            Interrupt::QDEC => 18,
-            Interrupt::COMP_LPCOMP => 19,
-            Interrupt::SWI0_EGU0 => 20,
-            Interrupt::SWI1_EGU1 => 21,
-            Interrupt::SWI2_EGU2 => 22,
-            Interrupt::SWI3_EGU3 => 23,
-            Interrupt::SWI4_EGU4 => 24,
-            Interrupt::SWI5_EGU5 => 25,
-            Interrupt::TIMER3 => 26,
-            Interrupt::TIMER4 => 27,
-            Interrupt::PWM0 => 28,
-            Interrupt::PDM => 29,
-            Interrupt::MWU => 32,
-            Interrupt::PWM1 => 33,
-            Interrupt::PWM2 => 34,
-            Interrupt::SPIM2_SPIS2_SPI2 => 35,
-            Interrupt::RTC2 => 36,
-            Interrupt::I2S => 37,
-            Interrupt::FPU => 38,
-        }
-    }
-}
+use peripherals::systick::systick_handler;
 
 /// Symbols that are exported from the linker script
 extern "C" {
@@ -169,6 +79,18 @@ extern "C" {
     /// End of the bss segment
     static mut __bss_end__: u32;
 
+    /// Start of the pre-initialization constructors
+    static __preinit_array_start: unsafe extern "C" fn();
+
+    /// End of the pre-initialization constructors
+    static __preinit_array_end: unsafe extern "C" fn();
+
+    /// Start of the static constructors
+    static __init_array_start: unsafe extern "C" fn();
+
+    /// End of the static constructors
+    static __init_array_end: unsafe extern "C" fn();
+
     /// Stack pointer, i.e., not a ordinary function
     fn __stack();
 }
@@ -179,30 +101,24 @@ extern "C" {
 #[cfg_attr(rustfmt, rustfmt_skip)]
 #[no_mangle]
 pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
-    __stack,                // Stack pointer
-    reset_handler,          // Reset handler
-    unhandled_interrupt,    // NMI
-    hardfault_handler,      // Hard Fault
-    unhandled_interrupt,    // CM3 Memory Management Fault
-    unhandled_interrupt,    // CM3 Bus Fault
-    unhandled_interrupt,    // CM3 Usage Fault
-    unhandled_interrupt,    // Reserved 
-    unhandled_interrupt,    // Reserved
-    unhandled_interrupt,    // Reserved
-    unhandled_interrupt,    // Reserved
-    svc_handler,            // SVCall
-    unhandled_interrupt,    // Reserved for debug
-    unhandled_interrupt,    // Reserved
-    unhandled_interrupt,    // PendSV
-    systick_handler,        // SysTick
+    __stack,                   // Stack pointer
+    reset_handler,             // Reset handler
+    unhandled_interrupt,       // NMI
+    fault::hardfault_handler,  // Hard Fault
+    fault::memmanage_handler,  // CM3 Memory Management Fault
+    fault::busfault_handler,   // CM3 Bus Fault
+    fault::usagefault_handler, // CM3 Usage Fault
+    unhandled_interrupt,       // Reserved
+    unhandled_interrupt,       // Reserved
+    unhandled_interrupt,       // Reserved
+    unhandled_interrupt,       // Reserved
+    syscall::svc_handler,      // SVCall
+    unhandled_interrupt,       // Reserved for debug
+    unhandled_interrupt,       // Reserved
+    kernel::pendsv_handler,    // PendSV
+    systick_handler,           // SysTick
 ];
 
-/// Interrupt Vector that is chip specific
-// TODO: Specify NRF52 specific here
-#[link_section = ".irqs"]
-#[no_mangle] // Ensures that the symbol is kept until the final binary
-pub static ISR: [unsafe extern "C" fn(); 80] = [generic_isr; 80];
-
 /// First entry point
 #[no_mangle]
 #[link_section = ".start"]
@@ -212,50 +128,53 @@ pub unsafe extern "C" fn reset_handler() {
         fn main(argc: isize, argv: *const *const u8) -> isize;
     }
 
+    // Hard-float code may be used from here on
+    fpu::enable();
+
+    // The application may be linked behind a bootloader, see `boot::jump_to_application`
+    (*cortex_m::peripheral::SCB::ptr())
+        .vtor
+        .write(BASE_VECTORS.as_ptr() as u32);
+
     crt0::init_data(&mut __etext, &mut __data_start__, &mut __data_end__);
     crt0::clear_bss(&mut __bss_start__, &mut __bss_end__);
 
-    // Start all clocks
-    let clock = &peripherals::clock::CLOCK;
+    #[cfg(feature = "stack-paint")]
+    crt0::paint_stack(
+        (stack::limit() + stack::GUARD_SIZE) as *mut u32,
+        stack::PAINT_PATTERN,
+    );
 
-    clock.low_stop();
-    clock.high_stop();
+    #[cfg(feature = "alloc")]
+    heap::init();
 
-    clock.low_set_source(peripherals::clock::LowClockSource::XTAL);
-    clock.low_start();
-    clock.high_set_source(peripherals::clock::HighClockSource::XTAL);
-    clock.high_start();
-    while !clock.low_started() {}
-    while !clock.high_started() {}
+    boot::capture();
+    crash::capture();
+    fault::enable_configurable_faults();
+    stack::enable_guard();
 
-    let mut peripherals = cortex_m::Peripherals::take().unwrap();
-    peripherals.NVIC.enable(Interrupt::UARTE0_UART0);
-    cortex_m::interrupt::enable();
+    // Start the low frequency clock, the HFXO is started on demand by `request_hfxo`
+    peripherals::clock::CLOCK.lock(|clock| {
+        clock.low_stop();
+        clock.high_stop();
 
-    main(0, core::ptr::null());
-}
+        clock.low_set_source(peripherals::clock::LowClockSource::XTAL);
+        clock.low_start();
+        while !clock.low_started() {}
+    });
 
-/// ARM Hard-Fault Handler
-pub unsafe extern "C" fn hardfault_handler() {
-    loop {}
-}
+    // Static constructors of linked C code, `.data` must be initialized first since the
+    // arrays are stored there
+    crt0::run_init_array(&__preinit_array_start, &__preinit_array_end);
+    crt0::run_init_array(&__init_array_start, &__init_array_end);
 
-/// ARM Hard-Fault Handler
-pub unsafe extern "C" fn systick_handler() {
-    loop {}
-}
+    // The NVIC is left untouched, `cortex_m::Peripherals::take` is available in `main`
+    cortex_m::interrupt::enable();
 
-/// ARM SysTick Handler
-pub unsafe extern "C" fn svc_handler() {
-    loop {}
+    main(0, core::ptr::null());
 }
 
 /// Unhandeled Interrupts
 pub unsafe extern "C" fn unhandled_interrupt() {
     loop {}
 }
-
-/// Generic Interrupt Handler
-pub unsafe extern "C" fn generic_isr() {
-    loop {}
-}
@@ -9,14 +9,39 @@
 //!     * 64 MHz internal oscillator (HFINT)
 //!     * 64 MHz crystal oscillator (HFXO)
 //!     * HFXO must be running the run the RADIO, NFC and calibration
+//!
+//! The HFXO draws roughly 250 µA so it is not started at reset. Drivers that need it
+//! call `request_hfxo()` and keep the returned `HfxoGuard` alive for as long as they
+//! need the crystal. The HFXO is stopped when the last guard is released.
+//! `request_hfxo_async()` waits for the crystal to start without busy-waiting. The
+//! requests only start and stop the HFXO with interrupts disabled and don't lock `CLOCK`,
+//! so they can be made and released while it is locked.
 
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use cortex_m::interrupt;
+use critical_section::Mutex;
+use executor::WakerSlot;
+use interrupt as irq;
+use peripherals::power::POWER;
 use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
 
 const CLOCK_BASE: usize = 0x4000_0000;
 
-pub static CLOCK: Clock = Clock::new();
+/// Clock Singleton, handed out by `Peripherals::take`
+pub(crate) static CLOCK: Mutex<Clock> = Mutex::new(Clock::new());
+
+/// Number of active HFXO requests
+static HFXO_USERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Waker of the pending `HfxoFuture`
+static HFXO_WAKER: WakerSlot = WakerSlot::new();
 
 /// Low frequency clock source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LowClockSource {
     RC = 0,
     XTAL = 1,
@@ -24,18 +49,51 @@ pub enum LowClockSource {
 }
 
 /// High frequency clock source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HighClockSource {
     RC = 0,
     XTAL = 1,
 }
 
+/// Speed of the trace port clock (TRACECLK)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TracePortSpeed {
+    MHZ32 = 0,
+    MHZ16 = 1,
+    MHZ8 = 2,
+    MHZ4 = 3,
+}
+
+impl TracePortSpeed {
+    /// Trace clock frequency in Hz
+    pub fn hz(&self) -> u32 {
+        match *self {
+            TracePortSpeed::MHZ32 => 32_000_000,
+            TracePortSpeed::MHZ16 => 16_000_000,
+            TracePortSpeed::MHZ8 => 8_000_000,
+            TracePortSpeed::MHZ4 => 4_000_000,
+        }
+    }
+}
+
+/// Pins that are used by the trace port
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceMux {
+    /// Trace pins are ordinary GPIOs
+    GPIO = 0,
+    /// Serial trace (SWO)
+    SERIAL = 1,
+    /// Parallel trace (TRACECLK and TRACEDATA[0..3])
+    PARALLEL = 2,
+}
+
 /// Clock struct
 pub struct Clock {
     registers: *const ClockRegisters,
 }
 
 impl Clock {
-    pub const fn new() -> Clock {
+    pub(crate) const fn new() -> Clock {
         Clock {
             registers: CLOCK_BASE as *const ClockRegisters,
         }
@@ -51,62 +109,287 @@ impl Clock {
     //     regs.intenclr.set(interrupt as u32);
     // }
 
-    pub unsafe fn high_start(&self) {
-        let regs = &*self.registers;
+    pub fn high_start(&self) {
+        let regs = unsafe { &*self.registers };
         regs.tasks_hfclkstart.write(Control::ENABLE.val(1));
     }
 
-    pub unsafe fn high_stop(&self) {
-        let regs = &*self.registers;
+    pub fn high_stop(&self) {
+        let regs = unsafe { &*self.registers };
         regs.tasks_hfclkstop.write(Control::ENABLE.val(1));
     }
 
-    pub unsafe fn high_started(&self) -> bool {
-        let regs = &*self.registers;
+    pub fn high_started(&self) -> bool {
+        let regs = unsafe { &*self.registers };
         regs.events_hfclkstarted.read(Status::READY) == 1
     }
 
-    pub unsafe fn high_set_source(&self, clk_source: HighClockSource) {
-        let regs = &*self.registers;
-        match clk_source {
-            HighClockSource::XTAL => regs.hfclkstat.write(HfClkStat::SRC::XTAL),
-            HighClockSource::RC => regs.hfclkstat.write(HfClkStat::SRC::RC),
+    /// Source of the high frequency clock that is currently running
+    ///
+    /// There is no source selection for HFCLK on nRF52, the HFXO is used while a
+    /// `HFCLKSTART` task is active and HFINT otherwise
+    pub fn high_source(&self) -> HighClockSource {
+        let regs = unsafe { &*self.registers };
+        match regs.hfclkstat.read(HfClkStat::SRC) {
+            1 => HighClockSource::XTAL,
+            _ => HighClockSource::RC,
         }
     }
 
-    pub unsafe fn high_running(&self) -> bool {
-        let regs = &*self.registers;
+    /// Whether the `HFCLKSTART` task has been triggered
+    pub fn high_start_triggered(&self) -> bool {
+        let regs = unsafe { &*self.registers };
+        regs.hfclkrun.matches_any(Status::READY::SET)
+    }
+
+    pub fn high_running(&self) -> bool {
+        let regs = unsafe { &*self.registers };
         regs.hfclkstat.matches_any(HfClkStat::STATE::RUNNING)
     }
 
-    pub unsafe fn low_start(&self) {
-        let regs = &*self.registers;
+    /// Handle CLOCK events, registered for `POWER_CLOCK` by `request_hfxo_async`
+    pub fn handle_interrupt(&self) {
+        let regs = unsafe { &*self.registers };
+        if regs.events_hfclkstarted.is_set(Status::READY) {
+            // The event is left set for `high_started`
+            regs.intenclr.write(Interrupt::HFCLKSTARTED::SET);
+            HFXO_WAKER.wake();
+        }
+    }
+
+    fn acquire_hfxo(&self) -> HfxoGuard {
+        interrupt::free(|_| {
+            if HFXO_USERS.load(Ordering::Relaxed) == 0 {
+                let regs = unsafe { &*self.registers };
+                regs.events_hfclkstarted.set(0);
+                self.high_start();
+            }
+            HFXO_USERS.fetch_add(1, Ordering::Relaxed);
+        });
+
+        HfxoGuard { _private: () }
+    }
+
+    /// Number of drivers that currently hold a HFXO request
+    pub fn hfxo_users(&self) -> usize {
+        HFXO_USERS.load(Ordering::Relaxed)
+    }
+
+    fn hfxo_running(&self) -> bool {
+        let regs = unsafe { &*self.registers };
+        regs.hfclkstat
+            .matches_all(HfClkStat::SRC::XTAL + HfClkStat::STATE::RUNNING)
+    }
+
+    /// Stop both clocks that `reset_handler` and `request_hfxo` start, called before
+    /// jumping to an application
+    pub(crate) fn reset(&self) {
+        let regs = unsafe { &*self.registers };
+        regs.intenclr.set(0xFFFF_FFFF);
+        self.high_stop();
+        self.low_stop();
+    }
+
+    pub fn low_start(&self) {
+        let regs = unsafe { &*self.registers };
         regs.tasks_lfclkstart.set(1);
     }
 
-    pub unsafe fn low_stop(&self) {
-        let regs = &*self.registers;
+    pub fn low_stop(&self) {
+        let regs = unsafe { &*self.registers };
         regs.tasks_lfclkstop.write(Control::ENABLE::SET);
     }
 
-    pub unsafe fn low_started(&self) -> bool {
-        let regs = &*self.registers;
+    pub fn low_started(&self) -> bool {
+        let regs = unsafe { &*self.registers };
         regs.events_lfclkstarted.matches_any(Status::READY::SET)
     }
 
-    pub unsafe fn low_running(&self) -> bool {
-        let regs = &*self.registers;
+    pub fn low_running(&self) -> bool {
+        let regs = unsafe { &*self.registers };
         regs.lfclkstat.matches_any(LfClkStat::STATE::RUNNING)
     }
 
-    pub unsafe fn low_set_source(&self, clk_source: LowClockSource) {
-        let regs = &*self.registers;
+    /// Source of the low frequency clock that is currently running
+    pub fn low_source(&self) -> LowClockSource {
+        let regs = unsafe { &*self.registers };
+        LowClockSource::from_bits(regs.lfclkstat.read(LfClkStat::SRC))
+    }
+
+    /// Source that was selected when the `LFCLKSTART` task was triggered
+    pub fn low_source_copy(&self) -> LowClockSource {
+        let regs = unsafe { &*self.registers };
+        LowClockSource::from_bits(regs.lfclksrccopy.read(LfClkSrcCopy::SRC))
+    }
+
+    /// Whether the `LFCLKSTART` task has been triggered
+    pub fn low_start_triggered(&self) -> bool {
+        let regs = unsafe { &*self.registers };
+        regs.lfclkrun.matches_any(Status::READY::SET)
+    }
+
+    /// Snapshot of the status of both clocks
+    pub fn status(&self) -> ClockStatus {
+        ClockStatus {
+            high_source: self.high_source(),
+            high_running: self.high_running(),
+            high_start_triggered: self.high_start_triggered(),
+            hfxo_users: self.hfxo_users(),
+            low_source: self.low_source(),
+            low_source_copy: self.low_source_copy(),
+            low_running: self.low_running(),
+            low_start_triggered: self.low_start_triggered(),
+        }
+    }
+
+    pub fn low_set_source(&self, clk_source: LowClockSource) {
+        let regs = unsafe { &*self.registers };
         match clk_source {
             LowClockSource::RC => regs.lfclksrc.write(LfClkSrc::SRC::RC),
             LowClockSource::XTAL => regs.lfclksrc.write(LfClkSrc::SRC::XTAL),
             LowClockSource::SYNTH => regs.lfclksrc.write(LfClkSrc::SRC::SYNTH),
         }
     }
+
+    /// Configure the trace port speed and which pins that are connected to it
+    pub fn set_trace_config(&self, speed: TracePortSpeed, mux: TraceMux) {
+        let regs = unsafe { &*self.registers };
+        regs.traceconfig.write(
+            TraceConfig::TracePortSpeed.val(speed as u32) + TraceConfig::TraceMux.val(mux as u32),
+        );
+    }
+}
+
+impl LowClockSource {
+    // `3` is reserved and never reported by the hardware
+    fn from_bits(bits: u32) -> LowClockSource {
+        match bits {
+            1 => LowClockSource::XTAL,
+            2 => LowClockSource::SYNTH,
+            _ => LowClockSource::RC,
+        }
+    }
+}
+
+/// Snapshot of the clock status registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockStatus {
+    /// HFCLKSTAT.SRC
+    pub high_source: HighClockSource,
+    /// HFCLKSTAT.STATE
+    pub high_running: bool,
+    /// HFCLKRUN.STATUS
+    pub high_start_triggered: bool,
+    /// Number of active HFXO requests
+    pub hfxo_users: usize,
+    /// LFCLKSTAT.SRC
+    pub low_source: LowClockSource,
+    /// LFCLKSRCCOPY.SRC
+    pub low_source_copy: LowClockSource,
+    /// LFCLKSTAT.STATE
+    pub low_running: bool,
+    /// LFCLKRUN.STATUS
+    pub low_start_triggered: bool,
+}
+
+impl fmt::Display for ClockStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "HFCLK: {:?} running: {} started: {} hfxo users: {}",
+            self.high_source, self.high_running, self.high_start_triggered, self.hfxo_users
+        )?;
+        write!(
+            f,
+            "LFCLK: {:?} (selected {:?}) running: {} started: {}",
+            self.low_source, self.low_source_copy, self.low_running, self.low_start_triggered
+        )
+    }
+}
+
+/// Request the HFXO and busy-wait until it is running
+///
+/// The crystal is kept running until every returned guard has been released
+pub fn request_hfxo() -> HfxoGuard {
+    let clock = Clock::new();
+    let guard = clock.acquire_hfxo();
+
+    // The crystal might have been started by another user that is still waiting
+    while !clock.hfxo_running() {}
+
+    guard
+}
+
+/// Request the HFXO, the returned future completes when the crystal is running
+///
+/// Registers a `POWER_CLOCK` handler that also forwards POWER events to
+/// `Power::handle_interrupt`
+pub fn request_hfxo_async() -> HfxoFuture {
+    irq::register_handler(irq::Interrupt::POWER_CLOCK, power_clock_interrupt);
+    irq::enable(irq::Interrupt::POWER_CLOCK);
+    HfxoFuture {
+        guard: Some(Clock::new().acquire_hfxo()),
+    }
+}
+
+/// Future returned by `request_hfxo_async`
+///
+/// Dropping the future before it completes releases the request
+pub struct HfxoFuture {
+    guard: Option<HfxoGuard>,
+}
+
+impl Future for HfxoFuture {
+    type Output = HfxoGuard;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<HfxoGuard> {
+        HFXO_WAKER.register(cx.waker());
+        let running = interrupt::free(|_| {
+            let clock = Clock::new();
+            let regs = unsafe { &*clock.registers };
+            regs.intenset.write(Interrupt::HFCLKSTARTED::SET);
+            // The crystal might have started before the interrupt was enabled
+            let running = clock.hfxo_running();
+            if running {
+                regs.intenclr.write(Interrupt::HFCLKSTARTED::SET);
+            }
+            running
+        });
+
+        if running {
+            let guard = self.guard.take();
+            Poll::Ready(guard.expect("HfxoFuture polled after completion"))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+fn power_clock_interrupt() {
+    CLOCK.lock(|clock| clock.handle_interrupt());
+    POWER.lock(|power| unsafe { power.handle_interrupt() });
+}
+
+/// Handle to a HFXO request, the crystal is released when this is dropped
+#[must_use]
+pub struct HfxoGuard {
+    _private: (),
+}
+
+impl HfxoGuard {
+    /// Release the HFXO request explicitly
+    pub fn release(self) {}
+}
+
+impl Drop for HfxoGuard {
+    fn drop(&mut self) {
+        interrupt::free(|_| {
+            if HFXO_USERS.fetch_sub(1, Ordering::Relaxed) == 1 {
+                // Fall back to HFINT when no user remains
+                Clock::new().high_stop();
+            }
+        });
+    }
 }
 
 struct ClockRegisters {
@@ -118,7 +401,7 @@ struct ClockRegisters {
     pub tasks_ctstart: WriteOnly<u32, Control::Register>,    // 0x014
     pub tasks_ctstop: WriteOnly<u32, Control::Register>,     // 0x018
     _reserved1: [u32; 57],                                   // 0x018 - 0x100
-    pub events_hfclkstarted: ReadOnly<u32, Status::Register>, // 0x100
+    pub events_hfclkstarted: ReadWrite<u32, Status::Register>, // 0x100
     pub events_lfclkstarted: ReadOnly<u32, Status::Register>, // 0x104
     _reserverd2: u32,                                        // 0x108
     pub events_done: ReadOnly<u32, Status::Register>,        // 0x10c
@@ -128,10 +411,10 @@ struct ClockRegisters {
     pub intenclr: ReadWrite<u32, Interrupt::Register>,       // 0x308
     _reserved4: [u32; 63],                                   // 0x30c - 0x408
     pub hfclkrun: ReadOnly<u32, Status::Register>,           // 0x408
-    pub hfclkstat: ReadWrite<u32, HfClkStat::Register>,      // 0x40c
+    pub hfclkstat: ReadOnly<u32, HfClkStat::Register>,       // 0x40c
     _reserved5: [u32; 1],                                    // 0x410
-    pub lfclkrun: ReadOnly<u32, Control::Register>,          // 0x414
-    pub lfclkstat: ReadWrite<u32, LfClkStat::Register>,      // 0x418
+    pub lfclkrun: ReadOnly<u32, Status::Register>,           // 0x414
+    pub lfclkstat: ReadOnly<u32, LfClkStat::Register>,       // 0x418
     pub lfclksrccopy: ReadOnly<u32, LfClkSrcCopy::Register>, // 0x41c
     _reserved6: [u32; 62],                                   // 0x420 - 0x518
     pub lfclksrc: ReadWrite<u32, LfClkSrc::Register>,        // 0x518
@@ -200,10 +483,9 @@ register_bitfields! [u32,
         TraceMux OFFSET(16) NUMBITS(2) [
             GPIO = 0,
             SERIAL = 1,
-            PARALELL = 2
+            PARALLEL = 2
         ]
     ]
 ];
 
 unsafe impl Send for Clock {}
-unsafe impl Sync for Clock {}
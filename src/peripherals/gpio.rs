@@ -2,17 +2,18 @@
 //! Simplest possible implementation
 //! Note, that the GPIOs are active low
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+use critical_section::Mutex;
 use tock_registers::registers::ReadWrite;
 
 const GPIO_BASE: usize = 0x5000_0000;
-#[allow(dead_code)]
-const NUM_GPIOTE: usize = 8;
-#[allow(dead_code)]
-const GPIOTE_BASE: usize = 0x4000_6000;
 const NUMBER_PINS: usize = 32;
 
-/// GPIO Singleton, use this as reference to access drivers
-pub static GPIO: Gpio = Gpio::new();
+/// GPIO Singleton, the pins are handed out by `Peripherals::take`
+pub(crate) static GPIO: Mutex<Gpio> = Mutex::new(Gpio::new());
+
+/// Pins that are claimed by another peripheral, one bit per pin
+static RESERVED_PINS: AtomicUsize = AtomicUsize::new(0);
 
 /// GPIO Registers
 #[repr(C)]
@@ -184,50 +185,194 @@ pub struct Gpio {
 }
 
 impl Gpio {
-    /// GPIO Constructor
-    pub const fn new() -> Self {
+    /// GPIO Constructor, use `GPIO` instead
+    pub(crate) const fn new() -> Self {
         Gpio {
             registers: GPIO_BASE as *const GpioRegisters,
         }
     }
 
     /// Configure a pin as output
-    pub unsafe fn make_output(&self, pin: u32) {
-        assert!(pin as usize <= NUMBER_PINS);
-        let regs = &*self.registers;
+    pub fn make_output(&self, pin: u32) {
+        assert!((pin as usize) < NUMBER_PINS);
+        let regs = unsafe { &*self.registers };
         regs.dirset.set(1 << pin);
         regs.pin_cnf[pin as usize].set(1);
     }
 
     /// Configure a pin as input
-    pub unsafe fn make_input(&self, pin: u32) {
-        assert!(pin as usize <= NUMBER_PINS);
-        let regs = &*self.registers;
+    pub fn make_input(&self, pin: u32) {
+        assert!((pin as usize) < NUMBER_PINS);
+        let regs = unsafe { &*self.registers };
         regs.dirclr.set(1 << pin);
         regs.pin_cnf[pin as usize].set(0);
     }
 
     /// Set pin high
-    pub unsafe fn set(&self, pin: u32) {
-        assert!(pin as usize <= NUMBER_PINS);
-        let regs = &*self.registers;
+    pub fn set(&self, pin: u32) {
+        assert!((pin as usize) < NUMBER_PINS);
+        let regs = unsafe { &*self.registers };
         regs.outset.set(1 << pin);
     }
 
     /// Set pin low
-    pub unsafe fn clear(&self, pin: u32) {
-        assert!(pin as usize <= NUMBER_PINS);
-        let regs = &*self.registers;
+    pub fn clear(&self, pin: u32) {
+        assert!((pin as usize) < NUMBER_PINS);
+        let regs = unsafe { &*self.registers };
         regs.outclr.set(1 << pin);
     }
 
+    /// Configure a pin as output with high drive strength on both levels
+    pub fn make_output_high_drive(&self, pin: u32) {
+        assert!((pin as usize) < NUMBER_PINS);
+        let regs = unsafe { &*self.registers };
+        regs.dirset.set(1 << pin);
+        regs.pin_cnf[pin as usize]
+            .write(PinConfig::DIR::Output + PinConfig::INPUT::Disconnect + PinConfig::DRIVE::H0H1);
+    }
+
+    /// Reserve a pin for another peripheral
+    ///
+    /// Returns `false` if the pin already was reserved
+    pub fn reserve(&self, pin: u32) -> bool {
+        assert!((pin as usize) < NUMBER_PINS);
+        let mask = 1 << pin;
+        RESERVED_PINS.fetch_or(mask, Ordering::Relaxed) & mask == 0
+    }
+
+    /// Release a reserved pin
+    pub fn release(&self, pin: u32) {
+        assert!((pin as usize) < NUMBER_PINS);
+        RESERVED_PINS.fetch_and(!(1 << pin), Ordering::Relaxed);
+    }
+
+    /// Check whether a pin is reserved by another peripheral
+    pub fn is_reserved(&self, pin: u32) -> bool {
+        assert!((pin as usize) < NUMBER_PINS);
+        RESERVED_PINS.load(Ordering::Relaxed) & (1 << pin) != 0
+    }
+
     /// Toggle pin
-    pub unsafe fn toggle(&self, pin: u32) {
-        assert!(pin as usize <= NUMBER_PINS);
-        let regs = &*self.registers;
+    pub fn toggle(&self, pin: u32) {
+        assert!((pin as usize) < NUMBER_PINS);
+        let regs = unsafe { &*self.registers };
         regs.out.set((1 << pin) ^ regs.out.get());
     }
+
+    /// Read the input level of a pin
+    pub fn read(&self, pin: u32) -> bool {
+        assert!((pin as usize) < NUMBER_PINS);
+        let regs = unsafe { &*self.registers };
+        regs.in_.get() & (1 << pin) != 0
+    }
 }
 
 unsafe impl Send for Gpio {}
-unsafe impl Sync for Gpio {}
+
+/// Owned GPIO pin
+pub struct Pin {
+    pin: u32,
+}
+
+impl Pin {
+    const fn new(pin: u32) -> Self {
+        Pin { pin }
+    }
+
+    /// Create a handle to `pin` that might be owned by someone else
+    pub unsafe fn steal(pin: u32) -> Self {
+        assert!((pin as usize) < NUMBER_PINS);
+        Pin::new(pin)
+    }
+
+    /// Pin number
+    pub fn number(&self) -> u32 {
+        self.pin
+    }
+
+    /// Configure the pin as output
+    pub fn make_output(&mut self) {
+        GPIO.lock(|gpio| gpio.make_output(self.pin));
+    }
+
+    /// Configure the pin as input
+    pub fn make_input(&mut self) {
+        GPIO.lock(|gpio| gpio.make_input(self.pin));
+    }
+
+    /// Set the pin high
+    pub fn set(&mut self) {
+        GPIO.lock(|gpio| gpio.set(self.pin));
+    }
+
+    /// Set the pin low
+    pub fn clear(&mut self) {
+        GPIO.lock(|gpio| gpio.clear(self.pin));
+    }
+
+    /// Toggle the pin
+    pub fn toggle(&mut self) {
+        GPIO.lock(|gpio| gpio.toggle(self.pin));
+    }
+
+    /// Read the input level of the pin
+    pub fn read(&self) -> bool {
+        GPIO.lock(|gpio| gpio.read(self.pin))
+    }
+}
+
+macro_rules! pins {
+    ($($field:ident: $pin:expr,)+) => {
+        /// Pins of GPIO port 0
+        pub struct Pins {
+            $(
+                #[doc = "P0."]
+                #[doc = stringify!($pin)]
+                pub $field: Pin,
+            )+
+        }
+
+        impl Pins {
+            pub(crate) const fn new() -> Self {
+                Pins {
+                    $($field: Pin::new($pin),)+
+                }
+            }
+        }
+    };
+}
+
+pins! {
+    p0_00: 0,
+    p0_01: 1,
+    p0_02: 2,
+    p0_03: 3,
+    p0_04: 4,
+    p0_05: 5,
+    p0_06: 6,
+    p0_07: 7,
+    p0_08: 8,
+    p0_09: 9,
+    p0_10: 10,
+    p0_11: 11,
+    p0_12: 12,
+    p0_13: 13,
+    p0_14: 14,
+    p0_15: 15,
+    p0_16: 16,
+    p0_17: 17,
+    p0_18: 18,
+    p0_19: 19,
+    p0_20: 20,
+    p0_21: 21,
+    p0_22: 22,
+    p0_23: 23,
+    p0_24: 24,
+    p0_25: 25,
+    p0_26: 26,
+    p0_27: 27,
+    p0_28: 28,
+    p0_29: 29,
+    p0_30: 30,
+    p0_31: 31,
+}
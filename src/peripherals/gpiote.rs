@@ -0,0 +1,241 @@
+//! GPIO tasks and events
+//!
+//! `wait_for` returns a future that completes on the next edge of a pin. Each waiting
+//! future uses one of the `NUM_CHANNELS` GPIOTE channels in event mode, the channel is
+//! released again when the future completes or is dropped.
+//!
+//! ```ignore
+//! let mut button = p.P0.p0_13;
+//! button.make_input();
+//! let mut gpiote = p.GPIOTE;
+//! executor::block_on(gpiote.lock(|gpiote| gpiote.wait_for(&mut button, Edge::Falling)));
+//! ```
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use critical_section::Mutex;
+use executor::WakerSlot;
+use interrupt;
+use peripherals::gpio;
+use tock_registers::registers::{ReadWrite, WriteOnly};
+
+const GPIOTE_BASE: usize = 0x4000_6000;
+
+/// Number of GPIOTE channels
+pub const NUM_CHANNELS: usize = 8;
+
+/// GPIOTE Singleton, handed out by `Peripherals::take`
+pub(crate) static GPIOTE: Mutex<Gpiote> = Mutex::new(Gpiote::new());
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+static WAKERS: [WakerSlot; NUM_CHANNELS] = [
+    WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(),
+    WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new(),
+];
+
+/// Edge that completes a `PinChange`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// Low to high
+    Rising,
+    /// High to low
+    Falling,
+    /// Any change
+    Any,
+}
+
+/// GPIOTE
+pub struct Gpiote {
+    registers: *const GpioteRegisters,
+    /// Channels in use, one bit per channel
+    allocated: u8,
+}
+
+impl Gpiote {
+    const fn new() -> Self {
+        Gpiote {
+            registers: GPIOTE_BASE as *const GpioteRegisters,
+            allocated: 0,
+        }
+    }
+
+    /// Future that completes at the next `edge` on `pin`
+    ///
+    /// The future locks `GPIOTE` whenever it is polled and waits for a free channel if all
+    /// of them are in use
+    pub fn wait_for<'a>(&self, pin: &'a mut gpio::Pin, edge: Edge) -> PinChange<'a> {
+        PinChange {
+            pin,
+            edge,
+            channel: None,
+        }
+    }
+
+    /// Handle GPIOTE events, registered for `GPIOTE` by `wait_for`
+    pub fn handle_interrupt(&self) {
+        let regs = unsafe { &*self.registers };
+        for (channel, waker) in WAKERS.iter().enumerate() {
+            if regs.event_in[channel].is_set(Event::READY) {
+                // The event is cleared by `PinChange::poll`
+                regs.intenclr.set(1 << channel);
+                waker.wake();
+            }
+        }
+    }
+
+    /// Configure a free channel to generate an event at `edge` on `pin`
+    fn listen(&mut self, pin: u32, edge: Edge) -> Option<usize> {
+        let channel = (0..NUM_CHANNELS).find(|channel| self.allocated & (1 << channel) == 0)?;
+        self.allocated |= 1 << channel;
+
+        let regs = unsafe { &*self.registers };
+        let polarity = match edge {
+            Edge::Rising => Config::POLARITY::LoToHi,
+            Edge::Falling => Config::POLARITY::HiToLo,
+            Edge::Any => Config::POLARITY::Toggle,
+        };
+        regs.config[channel].write(Config::MODE::Event + Config::PSEL.val(pin) + polarity);
+        // Configuring the channel might generate an event
+        regs.event_in[channel].set(0);
+        Some(channel)
+    }
+
+    /// Clear the event of `channel` and return whether it was set, enables the interrupt
+    /// otherwise
+    fn take_event(&self, channel: usize) -> bool {
+        let regs = unsafe { &*self.registers };
+        if regs.event_in[channel].is_set(Event::READY) {
+            regs.event_in[channel].set(0);
+            true
+        } else {
+            regs.intenset.set(1 << channel);
+            false
+        }
+    }
+
+    /// Release every channel, called before jumping to an application
+    pub(crate) fn reset(&mut self) {
+        for channel in 0..NUM_CHANNELS {
+            if self.allocated & (1 << channel) != 0 {
+                self.release(channel);
+            }
+        }
+    }
+
+    fn release(&mut self, channel: usize) {
+        let regs = unsafe { &*self.registers };
+        regs.intenclr.set(1 << channel);
+        regs.config[channel].write(Config::MODE::Disabled);
+        regs.event_in[channel].set(0);
+        self.allocated &= !(1 << channel);
+    }
+}
+
+/// Future returned by `Gpiote::wait_for`
+pub struct PinChange<'a> {
+    pin: &'a mut gpio::Pin,
+    edge: Edge,
+    channel: Option<usize>,
+}
+
+impl<'a> Future for PinChange<'a> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let channel = match self.channel {
+            Some(channel) => channel,
+            None => {
+                let (pin, edge) = (self.pin.number(), self.edge);
+                match GPIOTE.lock(|gpiote| gpiote.listen(pin, edge)) {
+                    Some(channel) => {
+                        interrupt::register_handler(interrupt::Interrupt::GPIOTE, gpiote_interrupt);
+                        interrupt::enable(interrupt::Interrupt::GPIOTE);
+                        self.channel = Some(channel);
+                        channel
+                    }
+                    None => {
+                        // Every channel is in use, poll again
+                        cx.waker().wake_by_ref();
+                        return Poll::Pending;
+                    }
+                }
+            }
+        };
+
+        WAKERS[channel].register(cx.waker());
+        if !GPIOTE.lock(|gpiote| gpiote.take_event(channel)) {
+            return Poll::Pending;
+        }
+
+        GPIOTE.lock(|gpiote| gpiote.release(channel));
+        WAKERS[channel].clear();
+        self.channel = None;
+        Poll::Ready(())
+    }
+}
+
+impl<'a> Drop for PinChange<'a> {
+    fn drop(&mut self) {
+        if let Some(channel) = self.channel {
+            GPIOTE.lock(|gpiote| gpiote.release(channel));
+            WAKERS[channel].clear();
+        }
+    }
+}
+
+fn gpiote_interrupt() {
+    GPIOTE.lock(|gpiote| gpiote.handle_interrupt());
+}
+
+unsafe impl Send for Gpiote {}
+
+#[repr(C)]
+struct GpioteRegisters {
+    pub task_out: [WriteOnly<u32, Task::Register>; NUM_CHANNELS], // 0x000-0x020
+    _reserved1: [u32; 4],                                         // 0x020-0x030
+    pub task_set: [WriteOnly<u32, Task::Register>; NUM_CHANNELS], // 0x030-0x050
+    _reserved2: [u32; 4],                                         // 0x050-0x060
+    pub task_clr: [WriteOnly<u32, Task::Register>; NUM_CHANNELS], // 0x060-0x080
+    _reserved3: [u32; 32],                                        // 0x080-0x100
+    pub event_in: [ReadWrite<u32, Event::Register>; NUM_CHANNELS], // 0x100-0x120
+    _reserved4: [u32; 23],                                        // 0x120-0x17C
+    pub event_port: ReadWrite<u32, Event::Register>,              // 0x17C-0x180
+    _reserved5: [u32; 97],                                        // 0x180-0x304
+    pub intenset: ReadWrite<u32>,                                 // 0x304-0x308
+    pub intenclr: ReadWrite<u32>,                                 // 0x308-0x30C
+    _reserved6: [u32; 129],                                       // 0x30C-0x510
+    pub config: [ReadWrite<u32, Config::Register>; NUM_CHANNELS], // 0x510-0x530
+}
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+register_bitfields! [u32,
+    /// Start task
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Read event
+    Event [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+
+    /// Channel configuration
+    Config [
+        MODE OFFSET(0) NUMBITS(2) [
+            Disabled = 0,
+            Event = 1,
+            Task = 3
+        ],
+        /// Pin number
+        PSEL OFFSET(8) NUMBITS(5),
+        POLARITY OFFSET(16) NUMBITS(2) [
+            None = 0,
+            LoToHi = 1,
+            HiToLo = 2,
+            Toggle = 3
+        ],
+        /// Initial value of the output in task mode
+        OUTINIT OFFSET(20) NUMBITS(1)
+    ]
+];
@@ -1,4 +1,108 @@
 //! Drivers for peripherals
+//!
+//! `Peripherals::take()` hands out the peripherals exactly once, mirroring
+//! `cortex_m::Peripherals`. Drivers that are shared with interrupt handlers are reached
+//! through a `Handle` which locks the driver for the duration of a closure:
+//!
+//! ```ignore
+//! let p = Peripherals::take().unwrap();
+//! let mut led = p.P0.p0_17;
+//! led.make_output();
+//! let mut uart = p.UARTE0.configure(
+//!     UartPins { txd: p.P0.p0_06, rxd: p.P0.p0_08, cts: p.P0.p0_07, rts: p.P0.p0_05 },
+//!     115_200,
+//! );
+//! uart.lock(|uart| uart.transmit(&BUFFER));
+//! ```
 pub mod clock;
 pub mod gpio;
+pub mod gpiote;
+pub mod power;
+pub mod systick;
 pub mod uart;
+
+use cortex_m::interrupt;
+use critical_section::Mutex;
+
+/// Set by `Peripherals::take`
+static mut TAKEN: bool = false;
+
+/// Stop the peripherals that the drivers have started, including EasyDMA transfers, and
+/// disable their interrupts
+///
+/// The locks are bypassed, interrupts must be disabled and the caller must never return to
+/// code that uses the drivers
+pub(crate) unsafe fn reset() {
+    uart::UART.steal().reset();
+    gpiote::GPIOTE.steal().reset();
+    clock::CLOCK.steal().reset();
+    power::POWER.steal().disable_pof_warning();
+}
+
+/// Owned handle to a driver, `P` are the pins that the peripheral consumes
+pub struct Handle<T: 'static, P = ()> {
+    driver: &'static Mutex<T>,
+    pins: P,
+}
+
+impl<T: 'static, P> Handle<T, P> {
+    pub(crate) fn new(driver: &'static Mutex<T>, pins: P) -> Self {
+        Handle { driver, pins }
+    }
+
+    /// Run `f` with exclusive access to the driver in a critical section
+    pub fn lock<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.driver.lock(f)
+    }
+
+    /// Pins that are used by the peripheral
+    pub fn pins(&self) -> &P {
+        &self.pins
+    }
+}
+
+/// All peripherals
+#[allow(non_snake_case)]
+pub struct Peripherals {
+    /// Clock control
+    pub CLOCK: Handle<clock::Clock>,
+    /// Power management
+    pub POWER: Handle<power::Power>,
+    /// SysTick tick and delays
+    pub SYSTICK: &'static systick::SysTick,
+    /// GPIO port 0
+    pub P0: gpio::Pins,
+    /// GPIO tasks and events
+    pub GPIOTE: Handle<gpiote::Gpiote>,
+    /// UARTE0, must be configured with its pins before use
+    pub UARTE0: uart::UARTE0,
+}
+
+impl Peripherals {
+    /// Returns all the peripherals once, `None` afterwards
+    pub fn take() -> Option<Self> {
+        interrupt::free(|_| {
+            if unsafe { TAKEN } {
+                None
+            } else {
+                Some(unsafe { Peripherals::steal() })
+            }
+        })
+    }
+
+    /// Unchecked version of `take`
+    pub unsafe fn steal() -> Self {
+        TAKEN = true;
+        Peripherals {
+            CLOCK: Handle::new(&clock::CLOCK, ()),
+            POWER: Handle::new(&power::POWER, ()),
+            SYSTICK: &systick::SYSTICK,
+            P0: gpio::Pins::new(),
+            GPIOTE: Handle::new(&gpiote::GPIOTE, ()),
+            UARTE0: uart::UARTE0::new(),
+        }
+    }
+}
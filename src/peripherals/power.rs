@@ -0,0 +1,383 @@
+//! Power peripheral driver
+//!
+//! The POWER block shares its base address and interrupt (`POWER_CLOCK`) with `Clock`
+//!
+//! System ON sub-modes:
+//!     * Low power, automatic power management (default)
+//!     * Constant latency, keeps the CPU wakeup latency and PPI response time constant
+//!
+//! System OFF is the deepest power saving mode, the system can only be woken up by a
+//! reset, a DETECT signal from GPIO, ANADETECT from LPCOMP or the NFC field
+
+use cortex_m::peripheral::SCB;
+use critical_section::Mutex;
+use interrupt::{AIRCR_PRIGROUP_MASK, AIRCR_SYSRESETREQ, AIRCR_VECTKEY};
+use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
+
+const POWER_BASE: usize = 0x4000_0000;
+
+/// Number of RAM blocks on nRF52832, each block has two sections
+pub const NUM_RAM_BLOCKS: usize = 8;
+
+/// Power Singleton, handed out by `Peripherals::take`
+pub(crate) static POWER: Mutex<Power> = Mutex::new(Power::new());
+
+/// System ON sub-mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubPowerMode {
+    /// Automatic power management
+    LowPower,
+    /// Constant latency
+    ConstantLatency,
+}
+
+/// Power-fail comparator threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PofThreshold {
+    V17 = 4,
+    V18 = 5,
+    V19 = 6,
+    V20 = 7,
+    V21 = 8,
+    V22 = 9,
+    V23 = 10,
+    V24 = 11,
+    V25 = 12,
+    V26 = 13,
+    V27 = 14,
+    V28 = 15,
+}
+
+/// Boot mode that is retained in GPREGRET across a soft reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootMode {
+    /// Ordinary boot, GPREGRET is zero after power-on
+    Normal,
+    /// Stay in the bootloader, same value as used by the Nordic DFU bootloader
+    Bootloader,
+    /// Boot the application in safe mode
+    SafeMode,
+    /// Application specific mode
+    Custom(u8),
+}
+
+impl BootMode {
+    /// Value stored in GPREGRET
+    pub fn bits(&self) -> u8 {
+        match *self {
+            BootMode::Normal => 0x00,
+            BootMode::Bootloader => 0xB1,
+            BootMode::SafeMode => 0x5A,
+            BootMode::Custom(bits) => bits,
+        }
+    }
+}
+
+impl From<u8> for BootMode {
+    fn from(bits: u8) -> Self {
+        match bits {
+            0x00 => BootMode::Normal,
+            0xB1 => BootMode::Bootloader,
+            0x5A => BootMode::SafeMode,
+            _ => BootMode::Custom(bits),
+        }
+    }
+}
+
+/// Decoded reset reason
+///
+/// More than one reason can be reported when RESETREAS hasn't been cleared since the
+/// previous reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResetReason(u32);
+
+impl ResetReason {
+    /// Raw value of RESETREAS
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Power-on or brown-out reset, i.e. no other reason is set
+    pub fn power_on(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Reset from the pin reset
+    pub fn pin(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+
+    /// Reset from the watchdog
+    pub fn watchdog(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    /// Soft reset, i.e. `AIRCR.SYSRESETREQ`
+    pub fn soft(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    /// Reset from CPU lock-up
+    pub fn lockup(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+
+    /// Wake up from System OFF by a DETECT signal from GPIO
+    pub fn off(&self) -> bool {
+        self.0 & (1 << 16) != 0
+    }
+
+    /// Wake up from System OFF by ANADETECT from LPCOMP
+    pub fn lpcomp(&self) -> bool {
+        self.0 & (1 << 17) != 0
+    }
+
+    /// Wake up from System OFF by entering debug interface mode
+    pub fn debug_interface(&self) -> bool {
+        self.0 & (1 << 18) != 0
+    }
+
+    /// Wake up from System OFF by the NFC field
+    pub fn nfc(&self) -> bool {
+        self.0 & (1 << 19) != 0
+    }
+}
+
+/// Power struct
+pub struct Power {
+    registers: *const PowerRegisters,
+    pofwarn: Option<fn()>,
+}
+
+impl Power {
+    pub(crate) const fn new() -> Self {
+        Power {
+            registers: POWER_BASE as *const PowerRegisters,
+            pofwarn: None,
+        }
+    }
+
+    /// Enter System OFF, this function never returns
+    pub unsafe fn system_off(&self) -> ! {
+        let regs = &*self.registers;
+        regs.systemoff.write(SystemOff::SYSTEMOFF::Enter);
+        // System OFF is entered when the write has been completed, in debug interface
+        // mode it is emulated and the CPU continues to execute
+        loop {
+            ::cortex_m::asm::wfe();
+        }
+    }
+
+    /// Select the System ON sub-mode
+    pub unsafe fn set_sub_power_mode(&self, mode: SubPowerMode) {
+        let regs = &*self.registers;
+        match mode {
+            SubPowerMode::LowPower => regs.tasks_lowpwr.write(Task::ENABLE::SET),
+            SubPowerMode::ConstantLatency => regs.tasks_constlat.write(Task::ENABLE::SET),
+        }
+    }
+
+    /// Read RESETREAS
+    pub unsafe fn reset_reason(&self) -> ResetReason {
+        let regs = &*self.registers;
+        ResetReason(regs.resetreas.get())
+    }
+
+    /// Clear the given reset reasons, RESETREAS is otherwise retained across resets
+    pub unsafe fn clear_reset_reason(&self, reason: ResetReason) {
+        let regs = &*self.registers;
+        regs.resetreas.set(reason.bits());
+    }
+
+    /// Read and clear RESETREAS
+    pub unsafe fn take_reset_reason(&self) -> ResetReason {
+        let reason = self.reset_reason();
+        self.clear_reset_reason(reason);
+        reason
+    }
+
+    /// Read the general purpose retention register GPREGRET
+    pub unsafe fn gpregret(&self) -> u8 {
+        let regs = &*self.registers;
+        regs.gpregret.get() as u8
+    }
+
+    /// Write the general purpose retention register GPREGRET
+    pub unsafe fn set_gpregret(&self, value: u8) {
+        let regs = &*self.registers;
+        regs.gpregret.set(u32::from(value));
+    }
+
+    /// Read the general purpose retention register GPREGRET2
+    pub unsafe fn gpregret2(&self) -> u8 {
+        let regs = &*self.registers;
+        regs.gpregret2.get() as u8
+    }
+
+    /// Write the general purpose retention register GPREGRET2
+    pub unsafe fn set_gpregret2(&self, value: u8) {
+        let regs = &*self.registers;
+        regs.gpregret2.set(u32::from(value));
+    }
+
+    /// Store `mode` in GPREGRET and perform a soft reset
+    pub unsafe fn reset_into(&self, mode: BootMode) -> ! {
+        self.set_gpregret(mode.bits());
+        system_reset()
+    }
+
+    /// Enable or disable the DCDC regulator
+    ///
+    /// The external LC filter must be mounted, which it is on the nRF52-DK
+    pub unsafe fn set_dcdc(&self, enable: bool) {
+        let regs = &*self.registers;
+        if enable {
+            regs.dcdcen.write(DcDcEn::DCDCEN::Enabled);
+        } else {
+            regs.dcdcen.write(DcDcEn::DCDCEN::Disabled);
+        }
+    }
+
+    /// Enable the power-fail comparator and call `callback` when the supply voltage
+    /// drops below `threshold`
+    ///
+    /// `callback` is invoked from `handle_interrupt` which must be called from the
+    /// `POWER_CLOCK` interrupt handler, `POWER` is locked while it runs
+    pub unsafe fn enable_pof_warning(&mut self, threshold: PofThreshold, callback: fn()) {
+        let regs = &*self.registers;
+        self.pofwarn = Some(callback);
+        regs.events_pofwarn.set(0);
+        regs.pofcon
+            .write(PofCon::POF::Enabled + PofCon::THRESHOLD.val(threshold as u32));
+        regs.intenset.write(Interrupt::POFWARN::SET);
+    }
+
+    /// Disable the power-fail comparator
+    pub unsafe fn disable_pof_warning(&mut self) {
+        let regs = &*self.registers;
+        regs.intenclr.write(Interrupt::POFWARN::SET);
+        regs.pofcon.write(PofCon::POF::Disabled);
+        self.pofwarn = None;
+    }
+
+    /// Handle POWER events, call this from the `POWER_CLOCK` interrupt handler
+    pub unsafe fn handle_interrupt(&self) {
+        let regs = &*self.registers;
+        if regs.events_pofwarn.is_set(Event::READY) {
+            regs.events_pofwarn.set(0);
+            if let Some(callback) = self.pofwarn {
+                callback();
+            }
+        }
+    }
+
+    /// Keep the sections `sections` (bit 0 for section 0, bit 1 for section 1) of RAM
+    /// block `block` powered in System ON
+    pub unsafe fn set_ram_power(&self, block: usize, sections: u32) {
+        assert!(block < NUM_RAM_BLOCKS);
+        let regs = &*self.registers;
+        regs.ram[block]
+            .power
+            .modify(RamPower::S0POWER.val(sections & 1) + RamPower::S1POWER.val(sections >> 1 & 1));
+    }
+
+    /// Keep the sections `sections` (bit 0 for section 0, bit 1 for section 1) of RAM
+    /// block `block` retained in System OFF
+    pub unsafe fn set_ram_retention(&self, block: usize, sections: u32) {
+        assert!(block < NUM_RAM_BLOCKS);
+        let regs = &*self.registers;
+        regs.ram[block].power.modify(
+            RamPower::S0RETENTION.val(sections & 1) + RamPower::S1RETENTION.val(sections >> 1 & 1),
+        );
+    }
+}
+
+/// Request a system reset via `AIRCR.SYSRESETREQ`
+pub fn system_reset() -> ! {
+    ::cortex_m::asm::dsb();
+    unsafe {
+        (*SCB::ptr())
+            .aircr
+            .modify(|r| AIRCR_VECTKEY | (r & AIRCR_PRIGROUP_MASK) | AIRCR_SYSRESETREQ);
+    }
+    ::cortex_m::asm::dsb();
+    loop {}
+}
+
+unsafe impl Send for Power {}
+
+#[repr(C)]
+struct RamRegisters {
+    pub power: ReadWrite<u32, RamPower::Register>, // 0x000
+    pub powerset: WriteOnly<u32, RamPower::Register>, // 0x004
+    pub powerclr: WriteOnly<u32, RamPower::Register>, // 0x008
+    _reserved: u32,                                // 0x00c
+}
+
+#[repr(C)]
+struct PowerRegisters {
+    _reserved1: [u32; 30],                                  // 0x000 - 0x078
+    pub tasks_constlat: WriteOnly<u32, Task::Register>,     // 0x078
+    pub tasks_lowpwr: WriteOnly<u32, Task::Register>,       // 0x07c
+    _reserved2: [u32; 34],                                  // 0x080 - 0x108
+    pub events_pofwarn: ReadWrite<u32, Event::Register>,    // 0x108
+    _reserved3: [u32; 2],                                   // 0x10c - 0x114
+    pub events_sleepenter: ReadWrite<u32, Event::Register>, // 0x114
+    pub events_sleepexit: ReadWrite<u32, Event::Register>,  // 0x118
+    _reserved4: [u32; 122],                                 // 0x11c - 0x304
+    pub intenset: ReadWrite<u32, Interrupt::Register>,      // 0x304
+    pub intenclr: ReadWrite<u32, Interrupt::Register>,      // 0x308
+    _reserved5: [u32; 61],                                  // 0x30c - 0x400
+    pub resetreas: ReadWrite<u32>,                          // 0x400
+    _reserved6: [u32; 9],                                   // 0x404 - 0x428
+    pub ramstatus: ReadOnly<u32>,                           // 0x428
+    _reserved7: [u32; 53],                                  // 0x42c - 0x500
+    pub systemoff: WriteOnly<u32, SystemOff::Register>,     // 0x500
+    _reserved8: [u32; 3],                                   // 0x504 - 0x510
+    pub pofcon: ReadWrite<u32, PofCon::Register>,           // 0x510
+    _reserved9: [u32; 2],                                   // 0x514 - 0x51c
+    pub gpregret: ReadWrite<u32>,                           // 0x51c
+    pub gpregret2: ReadWrite<u32>,                          // 0x520
+    _reserved10: [u32; 21],                                 // 0x524 - 0x578
+    pub dcdcen: ReadWrite<u32, DcDcEn::Register>,           // 0x578
+    _reserved11: [u32; 225],                                // 0x57c - 0x900
+    pub ram: [RamRegisters; NUM_RAM_BLOCKS],                // 0x900 - 0x980
+}
+
+register_bitfields! [u32,
+    Task [
+        ENABLE OFFSET(0) NUMBITS(1)
+    ],
+    Event [
+        READY OFFSET(0) NUMBITS(1)
+    ],
+    Interrupt [
+        POFWARN OFFSET(2) NUMBITS(1),
+        SLEEPENTER OFFSET(5) NUMBITS(1),
+        SLEEPEXIT OFFSET(6) NUMBITS(1)
+    ],
+    SystemOff [
+        SYSTEMOFF OFFSET(0) NUMBITS(1) [
+            Enter = 1
+        ]
+    ],
+    PofCon [
+        POF OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+        THRESHOLD OFFSET(1) NUMBITS(4) []
+    ],
+    DcDcEn [
+        DCDCEN OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+    RamPower [
+        S0POWER OFFSET(0) NUMBITS(1),
+        S1POWER OFFSET(1) NUMBITS(1),
+        S0RETENTION OFFSET(16) NUMBITS(1),
+        S1RETENTION OFFSET(17) NUMBITS(1)
+    ]
+];
@@ -0,0 +1,209 @@
+//! SysTick driver
+//!
+//! Provides a periodic tick with a monotonic tick counter and blocking delays based on
+//! the 64 MHz core clock. The delays work without the tick, the counter is then started
+//! free-running with the interrupt disabled.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cortex_m::interrupt;
+use cortex_m::peripheral::syst;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+const SYST_BASE: usize = 0xE000_E010;
+
+/// Frequency of the core clock that drives SysTick
+pub const CORE_CLOCK_HZ: u32 = 64_000_000;
+
+const CSR_ENABLE: u32 = 1 << 0;
+const CSR_TICKINT: u32 = 1 << 1;
+const CSR_CLKSOURCE: u32 = 1 << 2;
+
+/// The SysTick counter is 24 bits
+const MAX_RELOAD: u32 = 0x00FF_FFFF;
+
+/// SysTick Singleton, `Peripherals::take` hands out a reference to it
+pub(crate) static SYSTICK: SysTick = SysTick::new();
+
+/// Number of ticks since `SysTick::start`
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Tick rate in Hz, 0 when the tick is stopped
+static TICK_HZ: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of tick hooks, enough for the kernel and the executor timers
+pub const MAX_TICK_HOOKS: usize = 4;
+
+/// Called from the SysTick handler on every tick
+static mut TICK_HOOKS: [Option<fn()>; MAX_TICK_HOOKS] = [None; MAX_TICK_HOOKS];
+
+/// SysTick
+pub struct SysTick {
+    registers: *const syst::RegisterBlock,
+}
+
+impl SysTick {
+    const fn new() -> Self {
+        SysTick {
+            registers: SYST_BASE as *const syst::RegisterBlock,
+        }
+    }
+
+    /// Start the tick interrupt at `tick_hz`
+    ///
+    /// `tick_hz` must be between 4 Hz and 1 MHz
+    pub unsafe fn start(&self, tick_hz: u32) {
+        assert!(tick_hz > 0 && tick_hz <= 1_000_000, "unsupported tick rate");
+        let reload = CORE_CLOCK_HZ / tick_hz - 1;
+        assert!(reload <= MAX_RELOAD, "unsupported tick rate");
+
+        let regs = &*self.registers;
+        regs.csr.write(0);
+        TICKS.store(0, Ordering::Relaxed);
+        TICK_HZ.store(tick_hz as usize, Ordering::Relaxed);
+        regs.rvr.write(reload);
+        regs.cvr.write(0);
+        regs.csr.write(CSR_CLKSOURCE | CSR_TICKINT | CSR_ENABLE);
+    }
+
+    /// Stop the tick interrupt and the counter
+    pub unsafe fn stop(&self) {
+        let regs = &*self.registers;
+        regs.csr.write(0);
+        TICK_HZ.store(0, Ordering::Relaxed);
+    }
+
+    /// Call `hook` from the SysTick handler on every tick, next to the hooks that already
+    /// are installed
+    ///
+    /// Adding a hook twice has no effect, panics if `MAX_TICK_HOOKS` hooks are installed
+    pub fn add_tick_hook(&self, hook: fn()) {
+        interrupt::free(|_| unsafe {
+            if TICK_HOOKS.contains(&Some(hook)) {
+                return;
+            }
+            let slot = TICK_HOOKS.iter_mut().find(|slot| slot.is_none());
+            *slot.expect("too many tick hooks") = Some(hook);
+        });
+    }
+
+    /// Stop calling `hook` on every tick
+    pub fn remove_tick_hook(&self, hook: fn()) {
+        interrupt::free(|_| unsafe {
+            for slot in TICK_HOOKS.iter_mut().filter(|slot| **slot == Some(hook)) {
+                *slot = None;
+            }
+        });
+    }
+
+    /// Tick rate in Hz, 0 if the tick is stopped
+    pub fn tick_hz(&self) -> u32 {
+        TICK_HZ.load(Ordering::Relaxed) as u32
+    }
+
+    /// Monotonic tick counter, wraps around at `u32::MAX`
+    pub fn ticks(&self) -> u32 {
+        TICKS.load(Ordering::Relaxed) as u32
+    }
+
+    /// Milliseconds since `start`
+    ///
+    /// Derived from `ticks`, so it restarts at 0 when the tick counter wraps around at
+    /// `u32::MAX` ticks rather than at `u32::MAX` milliseconds
+    pub fn millis(&self) -> u32 {
+        let hz = self.tick_hz();
+        if hz == 0 {
+            return 0;
+        }
+        (u64::from(self.ticks()) * 1000 / u64::from(hz)) as u32
+    }
+
+    /// Busy-wait for `ms` milliseconds
+    pub fn delay_ms(&self, ms: u32) {
+        for _ in 0..ms {
+            self.delay_cycles(CORE_CLOCK_HZ / 1000);
+        }
+    }
+
+    /// Busy-wait for `us` microseconds
+    pub fn delay_us(&self, us: u32) {
+        // Split the delay so the number of cycles can't overflow
+        let ms = us / 1000;
+        self.delay_ms(ms);
+        self.delay_cycles((us - ms * 1000) * (CORE_CLOCK_HZ / 1_000_000));
+    }
+
+    fn delay_cycles(&self, cycles: u32) {
+        let regs = unsafe { &*self.registers };
+
+        if regs.csr.read() & CSR_ENABLE == 0 {
+            // Run the counter free-running without the tick interrupt
+            unsafe {
+                regs.rvr.write(MAX_RELOAD);
+                regs.cvr.write(0);
+                regs.csr.write(CSR_CLKSOURCE | CSR_ENABLE);
+            }
+        }
+
+        let period = regs.rvr.read() + 1;
+        let mut last = regs.cvr.read();
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            let now = regs.cvr.read();
+            // The counter counts down and wraps to the reload value
+            elapsed += if now <= last {
+                last - now
+            } else {
+                last + period - now
+            };
+            last = now;
+        }
+    }
+}
+
+// Implemented for the shared reference that `Peripherals::take` hands out
+impl<'a> DelayMs<u32> for &'a SysTick {
+    fn delay_ms(&mut self, ms: u32) {
+        SysTick::delay_ms(self, ms);
+    }
+}
+
+impl<'a> DelayMs<u16> for &'a SysTick {
+    fn delay_ms(&mut self, ms: u16) {
+        SysTick::delay_ms(self, u32::from(ms));
+    }
+}
+
+impl<'a> DelayMs<u8> for &'a SysTick {
+    fn delay_ms(&mut self, ms: u8) {
+        SysTick::delay_ms(self, u32::from(ms));
+    }
+}
+
+impl<'a> DelayUs<u32> for &'a SysTick {
+    fn delay_us(&mut self, us: u32) {
+        SysTick::delay_us(self, us);
+    }
+}
+
+impl<'a> DelayUs<u16> for &'a SysTick {
+    fn delay_us(&mut self, us: u16) {
+        SysTick::delay_us(self, u32::from(us));
+    }
+}
+
+impl<'a> DelayUs<u8> for &'a SysTick {
+    fn delay_us(&mut self, us: u8) {
+        SysTick::delay_us(self, u32::from(us));
+    }
+}
+
+unsafe impl Send for SysTick {}
+unsafe impl Sync for SysTick {}
+
+/// ARM SysTick Handler
+pub unsafe extern "C" fn systick_handler() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    for hook in TICK_HOOKS.iter().filter_map(|hook| *hook) {
+        hook();
+    }
+}
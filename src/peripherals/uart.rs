@@ -1,31 +1,93 @@
 //! UART
 
 use board;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use critical_section::Mutex;
+use executor::WakerSlot;
+use interrupt;
+use peripherals::gpio;
+use peripherals::Handle;
 use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
 
 const UART_BASE: u32 = 0x4000_2000;
 
-/// Uart Singleton
-pub static UART: Uart = Uart::new();
+/// Uart Singleton, handed out by `Peripherals::take` as `UARTE0`
+pub(crate) static UART: Mutex<Uart> = Mutex::new(Uart::new());
+
+/// Symbols that are exported from the linker script
+extern "C" {
+    /// Start of RAM
+    static __ram_start__: u8;
+
+    /// End of RAM
+    static __ram_end__: u8;
+}
+
+/// Waker of the pending `WriteFuture`
+static TX_WAKER: WakerSlot = WakerSlot::new();
+
+/// UARTE0 before it has been configured, see `Peripherals::take`
+pub struct UARTE0 {
+    _private: (),
+}
+
+impl UARTE0 {
+    pub(crate) const fn new() -> Self {
+        UARTE0 { _private: () }
+    }
+
+    /// Configure the UART to use `pins` and `baudrate`, the pins are owned by the
+    /// returned handle
+    pub fn configure(self, pins: UartPins, baudrate: u32) -> Handle<Uart, UartPins> {
+        UART.lock(|uart| unsafe {
+            let regs = &*uart.registers;
+            regs.pseltxd.write(Psel::PIN.val(pins.txd.number()));
+            regs.pselrxd.write(Psel::PIN.val(pins.rxd.number()));
+            regs.pselcts.write(Psel::PIN.val(pins.cts.number()));
+            regs.pselrts.write(Psel::PIN.val(pins.rts.number()));
+            uart.set_baudrate(baudrate);
+        });
+        Handle::new(&UART, pins)
+    }
+}
+
+/// Pins used by the UART, see `board` for the pins on the nRF52-DK
+pub struct UartPins {
+    /// Transmit
+    pub txd: gpio::Pin,
+    /// Receive
+    pub rxd: gpio::Pin,
+    /// Clear to send
+    pub cts: gpio::Pin,
+    /// Ready to send
+    pub rts: gpio::Pin,
+}
 
 /// Uart
 pub struct Uart {
     registers: *const UartTeRegisters,
+    /// A `WriteFuture` owns the transmitter
+    tx_busy: bool,
 }
 
 impl Uart {
-    /// Constructor
-    pub const fn new() -> Self {
+    /// Constructor, use `UART` instead
+    pub(crate) const fn new() -> Self {
         Uart {
             registers: UART_BASE as *const UartTeRegisters,
+            tx_busy: false,
         }
     }
 
     /// Initialize UART
-    /// Configures pins to be used by the UART and the baudrate
-    pub unsafe fn initialize(&self, baudrate: u32) {
-        self.set_pins();
-        self.set_baudrate(baudrate);
+    /// Configures the board pins to be used by the UART and the baudrate
+    pub(crate) fn initialize(&self, baudrate: u32) {
+        unsafe {
+            self.set_pins();
+            self.set_baudrate(baudrate);
+        }
     }
 
     unsafe fn set_pins(&self) {
@@ -68,31 +130,173 @@ impl Uart {
     }
 
     /// Transmit
-    pub unsafe fn transmit(&self, buffer: &'static [u8]) {
-        let regs = &*self.registers;
+    ///
+    /// Busy-waits until `buffer` has been sent, use `write` to avoid masking interrupts
+    /// for that long while `UART` is locked. Panics if a `WriteFuture` is transmitting.
+    pub fn transmit(&self, buffer: &'static [u8]) {
+        let regs = unsafe { &*self.registers };
 
+        assert!(!self.tx_busy, "UART is busy with a write");
         if buffer.is_empty() {
             return;
         }
 
+        unsafe { self.start_tx(buffer) };
+
+        // busy-wait
+        while regs.event_txstarted.matches_any(Event::READY::CLEAR) {}
+
+        // busy-wait
+        while regs.event_endtx.matches_any(Event::READY::CLEAR) {}
+    }
+
+    /// Transmit without blocking, the returned future completes when `buffer` has been
+    /// sent
+    ///
+    /// `buffer` must be in RAM. It is `'static` because EasyDMA keeps reading it if the
+    /// future is leaked. Dropping the future before it completes stops the transmission.
+    /// The future locks `UART` whenever it is polled and waits until other writes have
+    /// completed before it starts.
+    pub fn write(&self, buffer: &'static [u8]) -> WriteFuture {
+        WriteFuture {
+            buffer,
+            state: WriteState::Idle,
+        }
+    }
+
+    /// Handle UART events, registered for `UARTE0_UART0` by `write`
+    pub fn handle_interrupt(&self) {
+        let regs = unsafe { &*self.registers };
+        if regs.event_endtx.is_set(Event::READY) {
+            // The event is cleared by `WriteFuture::poll`
+            regs.intenclr.write(Interrupt::ENDTX::SET);
+            TX_WAKER.wake();
+        }
+    }
+
+    unsafe fn start_tx(&self, buffer: &[u8]) {
+        let regs = &*self.registers;
         let ptr = buffer.as_ptr() as u32;
-        assert!(ptr >= 0x2000_0000 && ptr <= 0x2001_0000, "DMA pointer must be in RAM");
+        let ram_start = &__ram_start__ as *const u8 as u32;
+        let ram_end = &__ram_end__ as *const u8 as u32;
+        assert!(
+            ptr >= ram_start && ptr + buffer.len() as u32 <= ram_end,
+            "DMA buffer must be in RAM"
+        );
         regs.txd_ptr.set(ptr as u32);
         regs.txd_maxcnt.set(buffer.len() as u32);
         regs.enable.write(Enable::ENABLE::ENABLED);
         regs.task_stoptx.write(Task::ENABLE::SET);
+        regs.event_txstarted.set(0);
+        regs.event_endtx.set(0);
         regs.task_starttx.write(Task::ENABLE::SET);
+    }
 
-        // busy-wait
-        while regs.event_txstarted.matches_any(Event::READY::CLEAR) {}
+    /// Stop any transmission and disable the UART, called before jumping to an application
+    pub(crate) unsafe fn reset(&mut self) {
+        let regs = &*self.registers;
+        regs.intenclr.set(0xFFFF_FFFF);
+        if regs.enable.matches_all(Enable::ENABLE::ENABLED) {
+            self.stop_tx();
+            regs.enable.write(Enable::ENABLE::DISABLED);
+        }
+        self.tx_busy = false;
+    }
 
+    /// Claim the transmitter for a `WriteFuture` and start sending `buffer`, returns
+    /// false if another write owns it
+    fn try_start_write(&mut self, buffer: &'static [u8]) -> bool {
+        if self.tx_busy {
+            return false;
+        }
+        self.tx_busy = true;
+        unsafe { self.start_tx(buffer) };
+        true
+    }
+
+    unsafe fn stop_tx(&self) {
+        let regs = &*self.registers;
+        regs.intenclr.write(Interrupt::ENDTX::SET);
+        regs.event_txstopped.set(0);
+        regs.task_stoptx.write(Task::ENABLE::SET);
         // busy-wait
-        while regs.event_endtx.matches_any(Event::READY::CLEAR) {}
+        while regs.event_txstopped.matches_any(Event::READY::CLEAR) {}
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WriteState {
+    Idle,
+    Transmitting,
+    Done,
+}
+
+/// Future returned by `Uart::write`
+pub struct WriteFuture {
+    buffer: &'static [u8],
+    state: WriteState,
+}
+
+impl Future for WriteFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.state == WriteState::Idle {
+            if self.buffer.is_empty() {
+                self.state = WriteState::Done;
+            } else {
+                let buffer = self.buffer;
+                if !UART.lock(|uart| uart.try_start_write(buffer)) {
+                    // Another write is transmitting, poll again
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                interrupt::register_handler(interrupt::Interrupt::UARTE0_UART0, uart_interrupt);
+                interrupt::enable(interrupt::Interrupt::UARTE0_UART0);
+                self.state = WriteState::Transmitting;
+            }
+        }
+
+        if self.state == WriteState::Transmitting {
+            TX_WAKER.register(cx.waker());
+            let done = UART.lock(|uart| {
+                let regs = unsafe { &*uart.registers };
+                if regs.event_endtx.is_set(Event::READY) {
+                    regs.event_endtx.set(0);
+                    uart.tx_busy = false;
+                    true
+                } else {
+                    regs.intenset.write(Interrupt::ENDTX::SET);
+                    false
+                }
+            });
+            if !done {
+                return Poll::Pending;
+            }
+            self.state = WriteState::Done;
+        }
+
+        Poll::Ready(())
     }
 }
 
+impl Drop for WriteFuture {
+    fn drop(&mut self) {
+        // Don't keep the UART busy for a future that is gone
+        if self.state == WriteState::Transmitting {
+            UART.lock(|uart| {
+                unsafe { uart.stop_tx() };
+                uart.tx_busy = false;
+            });
+        }
+    }
+}
+
+fn uart_interrupt() {
+    UART.lock(|uart| uart.handle_interrupt());
+}
+
 unsafe impl Send for Uart {}
-unsafe impl Sync for Uart {}
 
 #[repr(C)]
 struct UartTeRegisters {
@@ -108,15 +312,15 @@ struct UartTeRegisters {
     _reserved3: [u32; 2],                             // 0x108-0x110
     pub event_endrx: ReadOnly<u32, Event::Register>,  // 0x110-0x114
     _reserved4: [u32; 3],                             // 0x114-0x120
-    pub event_endtx: ReadOnly<u32, Event::Register>,  // 0x120-0x124
+    pub event_endtx: ReadWrite<u32, Event::Register>, // 0x120-0x124
     pub event_error: ReadOnly<u32, Event::Register>,  // 0x124-0x128
     _reserved6: [u32; 7],                             // 0x128-0x144
     pub event_rxto: ReadOnly<u32, Event::Register>,   // 0x144-0x148
     _reserved7: [u32; 1],                             // 0x148-0x14C
     pub event_rxstarted: ReadOnly<u32, Event::Register>, // 0x14C-0x150
-    pub event_txstarted: ReadOnly<u32, Event::Register>, // 0x150-0x154
+    pub event_txstarted: ReadWrite<u32, Event::Register>, // 0x150-0x154
     _reserved8: [u32; 1],                             // 0x154-0x158
-    pub event_txstopped: ReadOnly<u32, Event::Register>, // 0x158-0x15c
+    pub event_txstopped: ReadWrite<u32, Event::Register>, // 0x158-0x15c
     _reserved9: [u32; 41],                            // 0x15c-0x200
     pub shorts: ReadWrite<u32, Shorts::Register>,     // 0x200-0x204
     _reserved10: [u32; 64],                           // 0x204-0x304
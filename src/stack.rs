@@ -0,0 +1,154 @@
+//! Main stack guard
+//!
+//! `nrf52.ld` reserves `__stack_size__` bytes for the main stack between `__StackLimit`
+//! and `__StackTop`. By default the stack is at the top of RAM and grows down towards
+//! `.heap` and `.bss`, so `reset_handler` makes the lowest `GUARD_SIZE` bytes of the
+//! stack a no-access MPU region. An overflow then raises a MemManage fault instead of
+//! silently corrupting globals, and the fault handler reports it as a stack overflow.
+//! The code, SRAM and peripheral areas of the default memory map are recreated as
+//! background regions, so unprivileged code, see `syscall::enter_unprivileged`, keeps
+//! access to them.
+//!
+//! With the flip-link layout, `-C link-arg=--defsym=__flip_link__=1`, the stack is
+//! placed at the start of RAM below `.data` and an overflow runs off the start of RAM.
+//!
+//! With the `stack-paint` feature the unused part of the main stack is filled with
+//! `PAINT_PATTERN` at reset. `main_usage()` then finds the high-watermark by searching
+//! for the lowest word that has been overwritten. Once `kernel::start` has been called
+//! the main stack is only used by exceptions, the task stacks are reported by
+//! `kernel::stack_usage`.
+
+use cortex_m::asm;
+use cortex_m::peripheral::MPU;
+
+/// Size of the guard region at the bottom of the main stack
+pub const GUARD_SIZE: u32 = 32;
+
+/// Fill pattern of unused stack
+pub const PAINT_PATTERN: u32 = 0xCCCC_CCCC;
+
+/// MPU region used for the guard, the highest region takes priority on overlap
+const GUARD_REGION: u32 = 7;
+
+/// Background regions with full access, base address and attributes
+const BACKGROUND_REGIONS: [(u32, u32); 3] = [
+    // Code, normal memory
+    (0x0000_0000, MPU_RASR_C),
+    // SRAM, normal memory
+    (0x2000_0000, MPU_RASR_C | MPU_RASR_B),
+    // Peripherals, shareable device memory
+    (0x4000_0000, MPU_RASR_XN | MPU_RASR_S | MPU_RASR_B),
+];
+
+const MPU_CTRL_ENABLE: u32 = 1 << 0;
+const MPU_CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+const MPU_RASR_ENABLE: u32 = 1 << 0;
+const MPU_RASR_SIZE_32: u32 = 4 << 1;
+const MPU_RASR_SIZE_512M: u32 = 28 << 1;
+const MPU_RASR_B: u32 = 1 << 16;
+const MPU_RASR_C: u32 = 1 << 17;
+const MPU_RASR_S: u32 = 1 << 18;
+const MPU_RASR_AP_NO_ACCESS: u32 = 0 << 24;
+const MPU_RASR_AP_FULL_ACCESS: u32 = 3 << 24;
+const MPU_RASR_XN: u32 = 1 << 28;
+
+/// Symbols that are exported from the linker script
+extern "C" {
+    /// Lowest address of the main stack
+    static __StackLimit: u32;
+
+    /// Initial main stack pointer
+    static __StackTop: u32;
+}
+
+/// Lowest address of the main stack
+pub fn limit() -> u32 {
+    unsafe { &__StackLimit as *const u32 as u32 }
+}
+
+/// Initial main stack pointer
+pub fn top() -> u32 {
+    unsafe { &__StackTop as *const u32 as u32 }
+}
+
+/// Whether `addr` is in the guard region
+pub fn in_guard(addr: u32) -> bool {
+    addr.wrapping_sub(limit()) < GUARD_SIZE
+}
+
+/// Make the bottom of the main stack a no-access region and enable the MPU
+///
+/// The default memory map stays in place for privileged code and is mapped with full
+/// access for unprivileged code. The MPU is bypassed by the HardFault handler
+pub unsafe fn enable_guard() {
+    let mpu = &*MPU::ptr();
+    for (region, &(base, attributes)) in BACKGROUND_REGIONS.iter().enumerate() {
+        mpu.rnr.write(region as u32);
+        mpu.rbar.write(base);
+        mpu.rasr
+            .write(attributes | MPU_RASR_AP_FULL_ACCESS | MPU_RASR_SIZE_512M | MPU_RASR_ENABLE);
+    }
+
+    // `nrf52.ld` asserts that `__StackLimit` is aligned to the guard size
+    mpu.rnr.write(GUARD_REGION);
+    mpu.rbar.write(limit());
+    mpu.rasr.write(
+        MPU_RASR_XN
+            | MPU_RASR_AP_NO_ACCESS
+            | MPU_RASR_S
+            | MPU_RASR_C
+            | MPU_RASR_SIZE_32
+            | MPU_RASR_ENABLE,
+    );
+    mpu.ctrl.write(MPU_CTRL_PRIVDEFENA | MPU_CTRL_ENABLE);
+
+    asm::dsb();
+    asm::isb();
+}
+
+/// Disable the MPU and thereby the guard
+pub unsafe fn disable_guard() {
+    let mpu = &*MPU::ptr();
+    mpu.ctrl.write(0);
+
+    asm::dsb();
+    asm::isb();
+}
+
+/// Stack usage in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackUsage {
+    /// Usable size of the stack
+    pub size: u32,
+    /// Peak usage since reset
+    pub used: u32,
+}
+
+impl StackUsage {
+    /// Bytes that never have been used
+    pub fn headroom(&self) -> u32 {
+        self.size - self.used
+    }
+}
+
+/// Peak usage of the main stack, excluding the guard region
+#[cfg(feature = "stack-paint")]
+pub fn main_usage() -> StackUsage {
+    let bottom = (limit() + GUARD_SIZE) as *const u32;
+    let words = ((top() - limit() - GUARD_SIZE) / 4) as usize;
+    usage(unsafe { ::core::slice::from_raw_parts(bottom, words) })
+}
+
+/// Peak usage of a painted `stack`
+#[cfg(feature = "stack-paint")]
+pub fn usage(stack: &[u32]) -> StackUsage {
+    let unused = stack
+        .iter()
+        .take_while(|&&word| word == PAINT_PATTERN)
+        .count();
+    StackUsage {
+        size: (stack.len() * 4) as u32,
+        used: ((stack.len() - unused) * 4) as u32,
+    }
+}
@@ -0,0 +1,124 @@
+//! Supervisor calls
+//!
+//! `svc_handler` decodes the SVC number from the instruction before the stacked PC and
+//! dispatches to the syscall that is registered for that number. The arguments are passed
+//! in r0-r3 and the return value is written back to the stacked r0.
+//!
+//! ```ignore
+//! #![feature(asm)]
+//! #[macro_use]
+//! extern crate nrf52dk_rs;
+//!
+//! fn led_on(pin: u32, _: u32, _: u32, _: u32) -> u32 { ... }
+//!
+//! nrf52dk_rs::syscall::register(1, led_on);
+//! unsafe { nrf52dk_rs::syscall::enter_unprivileged() };
+//! let ret = syscall!(1, 17);
+//! ```
+
+use cortex_m::interrupt;
+use fault::ExceptionFrame;
+
+/// Number of syscall slots
+pub const NUM_SYSCALLS: usize = 32;
+
+/// Returned in r0 for SVC numbers without a registered syscall
+pub const UNKNOWN_SYSCALL: u32 = 0xFFFF_FFFF;
+
+/// Syscall, receives r0-r3 and returns the value for r0
+pub type Syscall = fn(u32, u32, u32, u32) -> u32;
+
+static mut SYSCALLS: [Option<Syscall>; NUM_SYSCALLS] = [None; NUM_SYSCALLS];
+
+/// Register `syscall` for SVC number `number`, replacing any previous syscall
+pub fn register(number: u8, syscall: Syscall) {
+    assert!((number as usize) < NUM_SYSCALLS, "SVC number out of range");
+    interrupt::free(|_| unsafe {
+        SYSCALLS[number as usize] = Some(syscall);
+    });
+}
+
+/// Remove the syscall for SVC number `number`
+pub fn unregister(number: u8) {
+    assert!((number as usize) < NUM_SYSCALLS, "SVC number out of range");
+    interrupt::free(|_| unsafe {
+        SYSCALLS[number as usize] = None;
+    });
+}
+
+/// Drop privileges of thread mode, only exceptions and syscalls run privileged afterwards
+///
+/// There is no way back to privileged thread mode except from an exception handler
+///
+/// # Safety
+///
+/// Unprivileged code can't mask interrupts, `cpsid` is ignored, so `interrupt::free` and
+/// every critical section built on it no longer exclude interrupt handlers. This includes
+/// the `Mutex`es behind `Peripherals`, the SysTick tick hooks and the executor timers.
+/// Accesses to the NVIC, SCB and the other system control registers fault. After this
+/// call, thread mode must only reach these through syscalls.
+pub unsafe fn enter_unprivileged() {
+    asm!("
+        mrs r0, CONTROL
+        orr r0, r0, #1
+        msr CONTROL, r0
+        isb
+    " ::: "r0", "memory" : "volatile");
+}
+
+/// ARM SVCall Handler
+///
+/// Passes the active stack pointer, selected by bit 2 of EXC_RETURN, to `svc_dispatch`
+#[naked]
+pub unsafe extern "C" fn svc_handler() {
+    asm!("
+        tst lr, #4
+        ite eq
+        mrseq r0, msp
+        mrsne r0, psp
+        b svc_dispatch
+    " :::: "volatile");
+}
+
+#[no_mangle]
+unsafe extern "C" fn svc_dispatch(frame: *mut ExceptionFrame) {
+    let frame = &mut *frame;
+    // The immediate is the low byte of the 16-bit SVC instruction preceding the stacked PC
+    let number = *((frame.pc - 2) as *const u8) as usize;
+
+    frame.r0 = match SYSCALLS.get(number).and_then(|syscall| *syscall) {
+        Some(syscall) => syscall(frame.r0, frame.r1, frame.r2, frame.r3),
+        None => UNKNOWN_SYSCALL,
+    };
+}
+
+/// Perform a supervisor call, `$number` must be a constant
+///
+/// Expands to inline assembly so the calling crate needs `#![feature(asm)]`
+#[macro_export]
+macro_rules! syscall {
+    ($number:expr) => {
+        syscall!($number, 0, 0, 0, 0)
+    };
+    ($number:expr, $a0:expr) => {
+        syscall!($number, $a0, 0, 0, 0)
+    };
+    ($number:expr, $a0:expr, $a1:expr) => {
+        syscall!($number, $a0, $a1, 0, 0)
+    };
+    ($number:expr, $a0:expr, $a1:expr, $a2:expr) => {
+        syscall!($number, $a0, $a1, $a2, 0)
+    };
+    ($number:expr, $a0:expr, $a1:expr, $a2:expr, $a3:expr) => {{
+        let ret: u32;
+        unsafe {
+            asm!("svc $1"
+                 : "={r0}"(ret)
+                 : "i"($number), "{r0}"($a0 as u32), "{r1}"($a1 as u32),
+                   "{r2}"($a2 as u32), "{r3}"($a3 as u32)
+                 : "memory"
+                 : "volatile");
+        }
+        ret
+    }};
+}
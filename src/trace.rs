@@ -0,0 +1,216 @@
+//! ARM trace support
+//!
+//! Routes the ITM stimulus ports to either the serial wire output (SWO) or the parallel
+//! trace port so logs can be streamed without using the UART.
+//!
+//! Note, on the nRF52-DK the trace pins are shared with LED2, LED4 and BUTTON2-4
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cortex_m::itm;
+use cortex_m::peripheral::itm::{RegisterBlock, Stim};
+use cortex_m::peripheral::{DCB, ITM, TPIU};
+use critical_section::Mutex;
+use peripherals::clock::{TraceMux, TracePortSpeed, CLOCK};
+use peripherals::gpio::{Pin, GPIO};
+
+/// TRACECLK
+const TRACECLK_PIN: u32 = 20;
+/// TRACEDATA[0] and SWO
+const TRACEDATA0_PIN: u32 = 18;
+/// TRACEDATA[1]
+const TRACEDATA1_PIN: u32 = 16;
+/// TRACEDATA[2]
+const TRACEDATA2_PIN: u32 = 15;
+/// TRACEDATA[3]
+const TRACEDATA3_PIN: u32 = 14;
+
+/// Pins that are reserved by `enable`, one bit per pin
+static TRACE_PINS: AtomicUsize = AtomicUsize::new(0);
+
+/// Pins handed to `enable`, returned by `disable`
+static OWNED_PINS: Mutex<Option<TracePins>> = Mutex::new(None);
+
+const DEMCR_TRCENA: u32 = 1 << 24;
+
+const ITM_LAR_UNLOCK: u32 = 0xC5AC_CE55;
+const ITM_TCR_ITMENA: u32 = 1 << 0;
+const ITM_TCR_SYNCENA: u32 = 1 << 2;
+const ITM_TCR_TRACE_BUS_ID: u32 = 1 << 16;
+
+const TPIU_SPPR_PARALLEL: u32 = 0;
+const TPIU_SPPR_NRZ: u32 = 2;
+const TPIU_FFCR_TRIGIN: u32 = 1 << 8;
+const TPIU_FFCR_ENFCONT: u32 = 1 << 1;
+
+/// Trace port configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TracePort {
+    /// Serial wire output with NRZ encoding
+    Serial {
+        /// SWO baudrate
+        baudrate: u32,
+    },
+    /// Parallel trace port
+    Parallel {
+        /// Number of TRACEDATA pins, 1, 2 or 4
+        width: u8,
+    },
+}
+
+/// Trace pins, taken from `Peripherals::P0`
+///
+/// SWO only needs `tracedata0`, the parallel port needs `traceclk` and one data pin per
+/// bit of its width
+pub struct TracePins {
+    /// TRACECLK, P0.20
+    pub traceclk: Option<Pin>,
+    /// TRACEDATA[0] and SWO, P0.18
+    pub tracedata0: Option<Pin>,
+    /// TRACEDATA[1], P0.16
+    pub tracedata1: Option<Pin>,
+    /// TRACEDATA[2], P0.15
+    pub tracedata2: Option<Pin>,
+    /// TRACEDATA[3], P0.14
+    pub tracedata3: Option<Pin>,
+}
+
+impl TracePins {
+    /// Pins that are present, one bit per pin
+    fn mask(&self) -> u32 {
+        [
+            &self.traceclk,
+            &self.tracedata0,
+            &self.tracedata1,
+            &self.tracedata2,
+            &self.tracedata3,
+        ]
+        .iter()
+        .filter_map(|pin| pin.as_ref())
+        .fold(0, |mask, pin| mask | 1 << pin.number())
+    }
+}
+
+/// Enable trace output and the ITM stimulus ports
+///
+/// Takes the trace pins and reserves them in `GPIO`, panics if a pin that `port` needs is
+/// missing or already reserved
+pub unsafe fn enable(port: TracePort, speed: TracePortSpeed, trace_pins: TracePins) {
+    let pins: &[u32] = match port {
+        TracePort::Serial { .. } => &[TRACEDATA0_PIN],
+        TracePort::Parallel { width: 1 } => &[TRACECLK_PIN, TRACEDATA0_PIN],
+        TracePort::Parallel { width: 2 } => &[TRACECLK_PIN, TRACEDATA0_PIN, TRACEDATA1_PIN],
+        TracePort::Parallel { width: 4 } => &[
+            TRACECLK_PIN,
+            TRACEDATA0_PIN,
+            TRACEDATA1_PIN,
+            TRACEDATA2_PIN,
+            TRACEDATA3_PIN,
+        ],
+        TracePort::Parallel { .. } => panic!("trace port width must be 1, 2 or 4"),
+    };
+
+    let given = trace_pins.mask();
+    assert!(
+        pins.iter().all(|pin| given & (1 << *pin) != 0),
+        "trace pin missing"
+    );
+
+    GPIO.lock(|gpio| {
+        // Check every pin first so nothing is left reserved when one is in use
+        assert!(
+            pins.iter().all(|pin| !gpio.is_reserved(*pin)),
+            "trace pin already in use"
+        );
+        for pin in pins {
+            gpio.reserve(*pin);
+            gpio.make_output_high_drive(*pin);
+            TRACE_PINS.fetch_or(1 << *pin, Ordering::Relaxed);
+        }
+    });
+    OWNED_PINS.lock(|owned| *owned = Some(trace_pins));
+
+    let mux = match port {
+        TracePort::Serial { .. } => TraceMux::SERIAL,
+        TracePort::Parallel { .. } => TraceMux::PARALLEL,
+    };
+    CLOCK.lock(|clock| clock.set_trace_config(speed, mux));
+
+    // Enable the trace subsystem before touching TPIU and ITM
+    let dcb = &*DCB::ptr();
+    dcb.demcr.modify(|w| w | DEMCR_TRCENA);
+
+    let tpiu = &*TPIU::ptr();
+    match port {
+        TracePort::Serial { baudrate } => {
+            assert!(baudrate > 0 && baudrate <= speed.hz());
+            tpiu.sppr.write(TPIU_SPPR_NRZ);
+            tpiu.acpr.write(speed.hz() / baudrate - 1);
+            // Bypass the formatter, SWO carries the ITM stream only
+            tpiu.ffcr.write(TPIU_FFCR_TRIGIN);
+        }
+        TracePort::Parallel { width } => {
+            tpiu.sppr.write(TPIU_SPPR_PARALLEL);
+            tpiu.acpr.write(0);
+            tpiu.cspsr.write(1 << (width - 1));
+            tpiu.ffcr.write(TPIU_FFCR_TRIGIN | TPIU_FFCR_ENFCONT);
+        }
+    }
+
+    let itm = &*ITM::ptr();
+    itm.lar.write(ITM_LAR_UNLOCK);
+    itm.tcr
+        .write(ITM_TCR_TRACE_BUS_ID | ITM_TCR_SYNCENA | ITM_TCR_ITMENA);
+    // Allow unprivileged access and enable all stimulus ports
+    itm.tpr.write(0);
+    itm.ter[0].write(0xFFFF_FFFF);
+}
+
+/// Disable trace output, release the trace pins in `GPIO` and return them
+pub unsafe fn disable() -> Option<TracePins> {
+    let itm = &*ITM::ptr();
+    itm.ter[0].write(0);
+    itm.tcr.write(0);
+
+    CLOCK.lock(|clock| clock.set_trace_config(TracePortSpeed::MHZ32, TraceMux::GPIO));
+
+    let pins = TRACE_PINS.swap(0, Ordering::Relaxed);
+    GPIO.lock(|gpio| {
+        for pin in 0..32 {
+            if pins & (1 << pin) != 0 {
+                gpio.make_input(pin);
+                gpio.release(pin);
+            }
+        }
+    });
+    OWNED_PINS.lock(|owned| owned.take())
+}
+
+/// Writer for an ITM stimulus port
+pub struct Stimulus {
+    port: usize,
+}
+
+impl Stimulus {
+    /// Stimulus port `port` (0 - 31)
+    pub fn new(port: usize) -> Self {
+        assert!(port < 32);
+        Stimulus { port }
+    }
+
+    fn stim(&mut self) -> &mut Stim {
+        unsafe { &mut (*(ITM::ptr() as *mut RegisterBlock)).stim[self.port] }
+    }
+
+    /// Write raw bytes to the stimulus port
+    pub fn write_all(&mut self, buffer: &[u8]) {
+        itm::write_all(self.stim(), buffer);
+    }
+}
+
+impl fmt::Write for Stimulus {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        itm::write_str(self.stim(), s);
+        Ok(())
+    }
+}